@@ -0,0 +1,74 @@
+use zenoh::bytes::ZBytes;
+use zenoh::sample::{Sample, SampleBuilder};
+use zenoh_ext::{z_deserialize, z_serialize};
+
+/********************/
+/*   Attachment     */
+/********************/
+/// Attachments are maps of string keys and values, serialized with the zenoh serialization format.
+pub(crate) type Attachment = Vec<(String, String)>;
+
+pub(crate) fn serialize(attachment: &Attachment) -> Option<ZBytes> {
+    (!attachment.is_empty()).then(|| z_serialize(attachment))
+}
+
+/// Returns `None` if `bytes` is not a serialized map of strings.
+pub(crate) fn deserialize(bytes: &ZBytes) -> Option<Attachment> {
+    z_deserialize(bytes).ok()
+}
+
+/// Parse a `key=value` pair
+pub(crate) fn parse_entry(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("expected KEY=VALUE pair, got `{s}`")),
+    }
+}
+
+/// Format an attachment as `key=value` pairs, or as text if it is not a map.
+pub(crate) fn display(bytes: &ZBytes) -> String {
+    match deserialize(bytes) {
+        Some(attachment) => attachment
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => String::from_utf8_lossy(&bytes.to_bytes()).into_owned(),
+    }
+}
+
+/// The entries of a serialized `attachment` to add the entry `key` to, which must be a map.
+pub(crate) fn entries(attachment: Option<&ZBytes>, key: &str) -> Result<Attachment, String> {
+    match attachment {
+        Some(bytes) => deserialize(bytes)
+            .ok_or_else(|| format!("the attachment must be a map of strings to add `{key}`")),
+        None => Ok(vec![]),
+    }
+}
+
+/// Add the entry `key` to a serialized `attachment`, which must be a map.
+pub(crate) fn insert(
+    attachment: Option<ZBytes>,
+    key: &str,
+    value: String,
+) -> Result<Option<ZBytes>, String> {
+    let mut entries = entries(attachment.as_ref(), key)?;
+    entries.push((key.to_string(), value));
+    Ok(serialize(&entries))
+}
+
+/// The value of the entry `key` in the attachment of `sample` along with the other entries,
+/// or `None` if there is no such entry.
+pub(crate) fn remove(sample: &Sample, key: &str) -> Option<(String, Attachment)> {
+    let mut entries = sample.attachment().and_then(deserialize)?;
+    let i = entries.iter().position(|(k, _)| k == key)?;
+    let (_, value) = entries.remove(i);
+    Some((value, entries))
+}
+
+/// Rebuild `sample` with the given attachment `entries`
+pub(crate) fn replace(sample: Sample, entries: &Attachment) -> Sample {
+    SampleBuilder::from(sample)
+        .attachment(serialize(entries))
+        .into()
+}
@@ -0,0 +1,42 @@
+use crate::utils::Checksum;
+use sha2::{Digest, Sha256};
+
+/********************/
+/*     Checksum     */
+/********************/
+/// The attachment key of the checksum sent with the EOF marker
+pub(crate) const ATTACHMENT_KEY: &str = "zat:checksum";
+
+/// A running checksum of the payloads of a stream.
+pub(crate) enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn new(checksum: Checksum) -> Self {
+        match checksum {
+            Checksum::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            Checksum::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(bytes),
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// The checksum of the bytes so far, as `<algorithm>:<hex digest>`
+    pub(crate) fn value(&self) -> String {
+        match self {
+            Hasher::Crc32(hasher) => format!("crc32:{:08x}", hasher.clone().finalize()),
+            Hasher::Sha256(hasher) => {
+                let digest = hasher.clone().finalize();
+                let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+                format!("sha256:{hex}")
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::attachment;
+use crate::utils::Compression;
+use std::io::{self, Read, Write};
+use zenoh::bytes::ZBytes;
+use zenoh::sample::Sample;
+
+/********************/
+/*   Compression    */
+/********************/
+/// The attachment key marking compressed payloads with their compression algorithm
+pub(crate) const ATTACHMENT_KEY: &str = "zat:compression";
+/// The maximum size of a decompressed payload
+const MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+
+fn name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::Zstd => "zstd",
+        Compression::Lz4 => "lz4",
+        Compression::Gzip => "gzip",
+    }
+}
+
+/// Compress `payload` as a standalone frame, e.g. decompressible with the `zstd` command.
+pub(crate) fn compress(compression: Compression, payload: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::Zstd => zstd::encode_all(payload, 0),
+        Compression::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+            encoder.write_all(payload)?;
+            encoder.finish().map_err(io::Error::other)
+        }
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(payload)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Decompress `payload` up to `MAX_DECOMPRESSED_LEN` bytes, so that a small payload
+/// cannot exhaust the memory of readers.
+fn decompress(name: &str, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let decoder: Box<dyn Read + '_> = match name {
+        "zstd" => Box::new(zstd::stream::read::Decoder::new(payload)?),
+        "lz4" => Box::new(lz4_flex::frame::FrameDecoder::new(payload)),
+        "gzip" => Box::new(flate2::read::GzDecoder::new(payload)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unknown compression `{name}`"),
+            ))
+        }
+    };
+    let mut buf = vec![];
+    decoder
+        .take(MAX_DECOMPRESSED_LEN as u64 + 1)
+        .read_to_end(&mut buf)?;
+    if buf.len() > MAX_DECOMPRESSED_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("decompressed payload exceeds {MAX_DECOMPRESSED_LEN} bytes"),
+        ));
+    }
+    Ok(buf)
+}
+
+/// Add the compression marker to a serialized `attachment`, which must be a map.
+pub(crate) fn mark(
+    attachment: Option<ZBytes>,
+    compression: Compression,
+) -> Result<Option<ZBytes>, String> {
+    attachment::insert(attachment, ATTACHMENT_KEY, name(compression).to_string())
+}
+
+/// Decompress the payload of `sample` if its attachment carries the compression marker,
+/// which is then removed from the attachment.
+pub(crate) fn decompress_sample(mut sample: Sample) -> Result<Sample, String> {
+    let Some((name, entries)) = attachment::remove(&sample, ATTACHMENT_KEY) else {
+        return Ok(sample);
+    };
+    let payload = decompress(&name, &sample.payload().to_bytes())
+        .map_err(|e| format!("{}: {e}", sample.key_expr()))?;
+    *sample.payload_mut() = ZBytes::from(payload);
+    Ok(attachment::replace(sample, &entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPRESSIONS: [Compression; 3] = [Compression::Zstd, Compression::Lz4, Compression::Gzip];
+
+    #[test]
+    fn round_trip() {
+        for compression in COMPRESSIONS {
+            let compressed = compress(compression, b"hello hello hello").unwrap();
+            let decompressed = decompress(name(compression), &compressed).unwrap();
+            assert_eq!(decompressed, b"hello hello hello");
+        }
+    }
+
+    #[test]
+    fn bomb() {
+        let zeros = vec![0u8; MAX_DECOMPRESSED_LEN + 1];
+        for compression in COMPRESSIONS {
+            let compressed = compress(compression, &zeros).unwrap();
+            assert!(compressed.len() < zeros.len() / 100);
+            let err = decompress(name(compression), &compressed).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
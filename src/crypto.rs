@@ -0,0 +1,341 @@
+use crate::attachment::{self, Attachment};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, Generate, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use zenoh::bytes::ZBytes;
+use zenoh::sample::Sample;
+use zenoh_ext::z_serialize;
+
+/********************/
+/*    Encryption    */
+/********************/
+// Encrypted payloads are prefixed with their 24-byte nonce, made of a random 16-byte stream id
+// chosen by each writer and a big-endian u64 counter. The key expression and the attachment
+// entries are authenticated along with the payload, so that samples cannot be replayed on another
+// key expression and their attachment, e.g. the compression marker, cannot be altered.
+// Samples without payload, i.e. deletes and EOF markers, carry a sealed payload in their
+// attachment so that they cannot be forged either. It is made of their secret entries, e.g.
+// the checksum of the stream which would otherwise tell about the plaintext.
+
+/// The attachment key of the base64 sealed payload of samples without payload
+pub(crate) const ATTACHMENT_KEY: &str = "zat:sealed";
+
+/// The length of the random ids of writer streams
+pub(crate) const STREAM_ID_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Load the pre-shared key from `path`, the key is the SHA-256 digest of the file content.
+pub(crate) fn load_key(path: &Path) -> io::Result<[u8; 32]> {
+    let content = std::fs::read(path)?;
+    if content.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "empty key file"));
+    }
+    Ok(Sha256::digest(&content).into())
+}
+
+/// A random stream id, distinguishing the writers using the same key
+pub(crate) fn stream_id() -> [u8; STREAM_ID_LEN] {
+    let mut stream = [0u8; STREAM_ID_LEN];
+    stream.copy_from_slice(&XNonce::generate()[..STREAM_ID_LEN]);
+    stream
+}
+
+/// The next counter expected from each stream, rejecting the out of order and replayed ones.
+#[derive(Default)]
+pub(crate) struct ReplayWindow {
+    next: HashMap<[u8; STREAM_ID_LEN], u64>,
+}
+
+impl ReplayWindow {
+    /// Whether `counter` is not older than the last accepted one of `stream`
+    pub(crate) fn is_fresh(&self, stream: &[u8; STREAM_ID_LEN], counter: u64) -> bool {
+        self.next.get(stream).is_none_or(|next| counter >= *next)
+    }
+
+    /// Move `stream` past `counter`, only once its sample is authenticated
+    pub(crate) fn accept(&mut self, stream: [u8; STREAM_ID_LEN], counter: u64) {
+        self.next.insert(stream, counter + 1);
+    }
+}
+
+/// The associated data authenticated along with a payload
+fn aad(keyexpr: &str, entries: &Attachment) -> Vec<u8> {
+    z_serialize(&(keyexpr, entries)).to_bytes().into_owned()
+}
+
+/// Encrypts the payloads of a writer.
+pub(crate) struct Sealer {
+    cipher: XChaCha20Poly1305,
+    stream: [u8; STREAM_ID_LEN],
+    counter: u64,
+}
+
+impl Sealer {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Sealer {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            stream: stream_id(),
+            counter: 0,
+        }
+    }
+
+    fn encrypt(&mut self, keyexpr: &str, payload: &[u8], entries: &Attachment) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..STREAM_ID_LEN].copy_from_slice(&self.stream);
+        nonce[STREAM_ID_LEN..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &XNonce::from(nonce),
+                Payload {
+                    msg: payload,
+                    aad: &aad(keyexpr, entries),
+                },
+            )
+            .unwrap();
+        [&nonce[..], &ciphertext].concat()
+    }
+
+    /// Encrypt the `payload` of a sample along with its `attachment`, which must be a map.
+    pub(crate) fn seal(
+        &mut self,
+        keyexpr: &str,
+        payload: &[u8],
+        attachment: Option<&ZBytes>,
+    ) -> Result<Vec<u8>, String> {
+        let entries = attachment::entries(attachment, ATTACHMENT_KEY)?;
+        Ok(self.encrypt(keyexpr, payload, &entries))
+    }
+
+    /// Add a sealed payload to `attachment`, which must be a map, made of the `secret` entries.
+    pub(crate) fn seal_attachment(
+        &mut self,
+        keyexpr: &str,
+        attachment: Option<ZBytes>,
+        secret: &Attachment,
+    ) -> Result<Option<ZBytes>, String> {
+        let mut entries = attachment::entries(attachment.as_ref(), ATTACHMENT_KEY)?;
+        let plaintext = attachment::serialize(secret).map_or(vec![], |s| s.to_bytes().into());
+        let sealed = BASE64_STANDARD.encode(self.encrypt(keyexpr, &plaintext, &entries));
+        entries.push((ATTACHMENT_KEY.to_string(), sealed));
+        Ok(attachment::serialize(&entries))
+    }
+}
+
+/// Decrypts the payloads of all the writers, rejecting replayed ones.
+pub(crate) struct Opener {
+    cipher: XChaCha20Poly1305,
+    streams: ReplayWindow,
+}
+
+impl Opener {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Opener {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            streams: ReplayWindow::default(),
+        }
+    }
+
+    fn decrypt(
+        &mut self,
+        keyexpr: &str,
+        payload: &[u8],
+        entries: &Attachment,
+    ) -> Result<Vec<u8>, String> {
+        if payload.len() < NONCE_LEN {
+            return Err("payload is not encrypted".to_string());
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let mut stream = [0u8; STREAM_ID_LEN];
+        stream.copy_from_slice(&nonce[..STREAM_ID_LEN]);
+        let counter = u64::from_be_bytes(nonce[STREAM_ID_LEN..].try_into().unwrap());
+        if !self.streams.is_fresh(&stream, counter) {
+            return Err("out of order or replayed payload".to_string());
+        }
+        let mut buf = [0u8; NONCE_LEN];
+        buf.copy_from_slice(nonce);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                &XNonce::from(buf),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad(keyexpr, entries),
+                },
+            )
+            .map_err(|_| "decryption failed".to_string())?;
+        // Only authenticated payloads move the stream forward
+        self.streams.accept(stream, counter);
+        Ok(plaintext)
+    }
+
+    /// Decrypt the payload of `sample`, authenticating its attachment along with it.
+    pub(crate) fn open(&mut self, mut sample: Sample) -> Result<Sample, String> {
+        let entries = match sample.attachment() {
+            Some(bytes) => attachment::deserialize(bytes).ok_or("attachment is not a map")?,
+            None => vec![],
+        };
+        let payload = sample.payload().to_bytes();
+        let plaintext = self.decrypt(sample.key_expr().as_str(), &payload, &entries)?;
+        *sample.payload_mut() = ZBytes::from(plaintext);
+        Ok(sample)
+    }
+
+    /// Open the sealed payload in the attachment of `sample`, which is replaced by
+    /// the secret entries it is made of.
+    pub(crate) fn open_attachment(&mut self, sample: Sample) -> Result<Sample, String> {
+        let (sealed, mut entries) =
+            attachment::remove(&sample, ATTACHMENT_KEY).ok_or("sample is not sealed")?;
+        let sealed = BASE64_STANDARD
+            .decode(sealed)
+            .map_err(|_| "invalid sealed payload")?;
+        let plaintext = self.decrypt(sample.key_expr().as_str(), &sealed, &entries)?;
+        if !plaintext.is_empty() {
+            let secret = attachment::deserialize(&ZBytes::from(plaintext))
+                .ok_or("invalid sealed payload")?;
+            entries.extend(secret);
+        }
+        Ok(attachment::replace(sample, &entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zenoh::key_expr::KeyExpr;
+    use zenoh::sample::SampleBuilder;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn put(keyexpr: &'static str, payload: Vec<u8>, attachment: Option<ZBytes>) -> Sample {
+        SampleBuilder::put(KeyExpr::new(keyexpr).unwrap(), payload)
+            .attachment(attachment)
+            .into()
+    }
+
+    fn delete(keyexpr: &'static str, attachment: Option<ZBytes>) -> Sample {
+        SampleBuilder::delete(KeyExpr::new(keyexpr).unwrap())
+            .attachment(attachment)
+            .into()
+    }
+
+    fn entries(sample: &Sample) -> Attachment {
+        sample
+            .attachment()
+            .and_then(attachment::deserialize)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let attachment = attachment::serialize(&vec![("a".to_string(), "b".to_string())]);
+        let sealed = sealer
+            .seal("foo/bar", b"hello", attachment.as_ref())
+            .unwrap();
+        assert_ne!(&sealed[NONCE_LEN..], b"hello");
+        let sample = opener.open(put("foo/bar", sealed, attachment)).unwrap();
+        assert_eq!(&*sample.payload().to_bytes(), b"hello");
+        assert_eq!(entries(&sample), [("a".to_string(), "b".to_string())]);
+    }
+
+    #[test]
+    fn wrong_key() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&[8; 32]));
+        let sealed = sealer.seal("foo/bar", b"hello", None).unwrap();
+        assert!(opener.open(put("foo/bar", sealed, None)).is_err());
+    }
+
+    #[test]
+    fn wrong_keyexpr() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let sealed = sealer.seal("foo/bar", b"hello", None).unwrap();
+        assert!(opener.open(put("foo/baz", sealed, None)).is_err());
+    }
+
+    #[test]
+    fn tampered() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let mut sealed = sealer.seal("foo/bar", b"hello", None).unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        assert!(opener.open(put("foo/bar", sealed, None)).is_err());
+        assert!(opener
+            .open(put("foo/bar", b"short".to_vec(), None))
+            .is_err());
+
+        // The attachment entries are authenticated, e.g. the compression marker
+        let attachment =
+            attachment::serialize(&vec![("zat:compression".to_string(), "zstd".to_string())]);
+        let sealed = sealer
+            .seal("foo/bar", b"hello", attachment.as_ref())
+            .unwrap();
+        assert!(opener.open(put("foo/bar", sealed.clone(), None)).is_err());
+        let altered =
+            attachment::serialize(&vec![("zat:compression".to_string(), "gzip".to_string())]);
+        assert!(opener.open(put("foo/bar", sealed, altered)).is_err());
+    }
+
+    #[test]
+    fn replayed() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let first = sealer.seal("foo/bar", b"1", None).unwrap();
+        let second = sealer.seal("foo/bar", b"2", None).unwrap();
+        let third = sealer.seal("foo/bar", b"3", None).unwrap();
+        assert!(opener.open(put("foo/bar", second.clone(), None)).is_ok());
+        assert!(opener.open(put("foo/bar", second, None)).is_err());
+        // Out of order
+        assert!(opener.open(put("foo/bar", first, None)).is_err());
+        assert!(opener.open(put("foo/bar", third, None)).is_ok());
+
+        // Writers are tracked independently
+        let mut other = Sealer::new(&KEY);
+        let sealed = other.seal("foo/bar", b"1", None).unwrap();
+        assert!(opener.open(put("foo/bar", sealed, None)).is_ok());
+    }
+
+    #[test]
+    fn sealed_attachment() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let secret = vec![("zat:checksum".to_string(), "crc32:01234567".to_string())];
+        let attachment = attachment::serialize(&vec![("a".to_string(), "b".to_string())]);
+        let sealed = sealer
+            .seal_attachment("foo/bar", attachment, &secret)
+            .unwrap();
+        // The secret entries are not in clear
+        let sample = delete("foo/bar", sealed);
+        assert!(!entries(&sample).contains(&secret[0]));
+        let opened = opener.open_attachment(sample.clone()).unwrap();
+        assert_eq!(
+            entries(&opened),
+            [("a".to_string(), "b".to_string()), secret[0].clone()]
+        );
+        // Replayed
+        assert!(opener.open_attachment(sample).is_err());
+
+        let sealed = sealer.seal_attachment("foo/bar", None, &vec![]).unwrap();
+        assert!(opener.open_attachment(delete("foo/baz", sealed)).is_err());
+        // Forged deletes
+        assert_eq!(
+            opener.open_attachment(delete("foo/bar", None)).unwrap_err(),
+            "sample is not sealed"
+        );
+    }
+
+    #[test]
+    fn tampered_attachment() {
+        let (mut sealer, mut opener) = (Sealer::new(&KEY), Opener::new(&KEY));
+        let sealed = sealer.seal_attachment("foo/bar", None, &vec![]).unwrap();
+        let mut entries = attachment::deserialize(sealed.as_ref().unwrap()).unwrap();
+        entries.insert(
+            0,
+            ("zat:checksum".to_string(), "crc32:01234567".to_string()),
+        );
+        let sample = delete("foo/bar", attachment::serialize(&entries));
+        assert!(opener.open_attachment(sample).is_err());
+    }
+}
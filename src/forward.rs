@@ -0,0 +1,256 @@
+use crate::utils::{ForwardParams, ForwardRole};
+use crate::CHANNEL_SIZE;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::time::{Duration, Instant};
+use zenoh::key_expr::KeyExpr;
+use zenoh::pubsub::Subscriber;
+use zenoh::qos::{CongestionControl, Reliability};
+use zenoh::query::Query;
+use zenoh::sample::{Sample, SampleKind};
+use zenoh::{Session, Wait};
+
+/********************/
+/*     Forward      */
+/********************/
+// The listening side opens each accepted connection by querying its own key expression
+// `<keyexpr>/@zat/tcp/<zid>/<n>`, the target side replies once connected to the target.
+// Bytes are then published on `up` (towards the target) and `down` (towards the client)
+// under the connection key expression, and a delete on either signals a half-close.
+// Both sides declare a liveliness token to abort the connection if the other one goes away.
+
+/// The suffix of the connection key expressions
+const TCP_SUFFIX: &str = "@zat/tcp";
+/// The buffer size to read connections on
+const BUFFER_SIZE: usize = 32768;
+/// How long the bytes still in flight are received once the other side went away,
+/// as the undeclaration of its token may overtake them
+const LINGER: Duration = Duration::from_secs(1);
+/// How long connecting to the target may take, less than the timeout of the opening query
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The events of a forwarded connection
+enum Event {
+    /// Bytes, or a half-close on delete
+    Data(Sample),
+    /// The liveliness token of the other side was undeclared, i.e. it went away
+    PeerLost(Sample),
+    /// All the bytes read from the connection were published
+    Sent,
+}
+
+/// The events received on a connection, with the subscribers they are received from
+struct Inbound {
+    events: Receiver<Event>,
+    /// For the sending side to signal [`Event::Sent`]
+    local: SyncSender<Event>,
+    _data: Subscriber<()>,
+    _peer: Subscriber<()>,
+}
+
+/// Forward TCP connections accepted on the bind address, or connect them to the target
+pub(crate) fn forward(s: &Session, params: ForwardParams) {
+    let ForwardParams { keyexpr, role } = params;
+    match role {
+        ForwardRole::Bind(addr) => listen(s, &keyexpr, &addr),
+        ForwardRole::Target(addr) => serve(s, &keyexpr, &addr),
+    }
+}
+
+/// Accept TCP connections and open them on the target side
+fn listen(s: &Session, keyexpr: &KeyExpr, addr: &str) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {addr}: {e}");
+            std::process::exit(-1);
+        }
+    };
+    let prefix = keyexpr.join(&format!("{TCP_SUFFIX}/{}", s.zid())).unwrap();
+    for (n, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        let s = s.clone();
+        let conn = prefix.join(&n.to_string()).unwrap();
+        std::thread::spawn(move || {
+            // Bytes sent by the target on connection are received once it replied
+            let inbound = subscribe(&s, &conn, "down", "target");
+            let _token = s
+                .liveliness()
+                .declare_token(conn.join("client").unwrap())
+                .wait()
+                .unwrap();
+            let replies = s.get(&conn).wait().unwrap();
+            match replies.recv().map(|reply| reply.into_result()) {
+                Ok(Ok(_)) => tunnel(&s, stream, &conn, "up", inbound),
+                Ok(Err(err)) => eprintln!(
+                    "Failed to open {conn}: {}",
+                    String::from_utf8_lossy(&err.payload().to_bytes())
+                ),
+                Err(_) => eprintln!("Failed to open {conn}: no target"),
+            }
+        });
+    }
+}
+
+/// Connect the connections opened by the listening side to the target
+fn serve(s: &Session, keyexpr: &KeyExpr, addr: &str) {
+    let q = s
+        .declare_queryable(keyexpr.join(&format!("{TCP_SUFFIX}/*/*")).unwrap())
+        .wait()
+        .unwrap();
+    while let Ok(query) = q.recv() {
+        let s = s.clone();
+        let addr = addr.to_string();
+        std::thread::spawn(move || open(&s, query, &addr));
+    }
+}
+
+/// Connect to one of the addresses `addr` resolves to within `CONNECT_TIMEOUT`
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for addr in addr.to_socket_addrs()? {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Connect to `addr` for the connection queried by `query`, and reply once connected
+fn open(s: &Session, query: Query, addr: &str) {
+    let conn = query.key_expr().clone().into_owned();
+    let stream = match connect(addr) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect {conn} to {addr}: {e}");
+            query
+                .reply_err(format!("failed to connect to {addr}: {e}"))
+                .wait()
+                .unwrap();
+            return;
+        }
+    };
+    let inbound = subscribe(s, &conn, "up", "client");
+    // The client gave up on the connection if it went away while connecting
+    let client = s.liveliness().get(conn.join("client").unwrap()).wait();
+    if !client.unwrap().iter().any(|reply| reply.result().is_ok()) {
+        eprintln!("Failed to open {conn}: the client went away");
+        return;
+    }
+    let _token = s
+        .liveliness()
+        .declare_token(conn.join("target").unwrap())
+        .wait()
+        .unwrap();
+    query.reply(&conn, vec![]).wait().unwrap();
+    drop(query);
+    tunnel(s, stream, &conn, "down", inbound);
+}
+
+/// Subscribe to the bytes received on `<conn>/<rx>`, and to the loss of the `peer` side
+fn subscribe(s: &Session, conn: &KeyExpr, rx: &str, peer: &str) -> Inbound {
+    let (tx, events) = std::sync::mpsc::sync_channel::<Event>(CHANNEL_SIZE);
+    let (tx_peer, local) = (tx.clone(), tx.clone());
+    let _data = s
+        .declare_subscriber(conn.join(rx).unwrap())
+        .callback(move |sample| {
+            let _ = tx.send(Event::Data(sample));
+        })
+        .wait()
+        .unwrap();
+    let _peer = s
+        .liveliness()
+        .declare_subscriber(conn.join(peer).unwrap())
+        .history(true)
+        .callback(move |sample| {
+            if sample.kind() == SampleKind::Delete {
+                let _ = tx_peer.send(Event::PeerLost(sample));
+            }
+        })
+        .wait()
+        .unwrap();
+    Inbound {
+        events,
+        local,
+        _data,
+        _peer,
+    }
+}
+
+/// Publish the bytes read from `stream` on `<conn>/<tx>` and write the received ones to it,
+/// until both directions are closed
+fn tunnel(s: &Session, mut stream: TcpStream, conn: &KeyExpr, tx: &str, inbound: Inbound) {
+    let p = s
+        .declare_publisher(conn.join(tx).unwrap())
+        .reliability(Reliability::Reliable)
+        .congestion_control(CongestionControl::Block)
+        .wait()
+        .unwrap();
+
+    let mut writer = stream.try_clone().unwrap();
+    let local = inbound.local.clone();
+    // The subscribers live as long as the receiving side, which watches the other side
+    // until both directions are closed
+    let receiving = std::thread::spawn(move || {
+        let (mut received, mut sent) = (false, false);
+        // The token of the other side once it went away
+        let mut lost: Option<Sample> = None;
+        while !(received && sent) {
+            let event = match &lost {
+                Some(token) => match inbound.events.recv_timeout(LINGER) {
+                    Ok(event) => event,
+                    // Unblock the sending side
+                    Err(_) => {
+                        eprintln!("Aborting the connection: {} went away", token.key_expr());
+                        let _ = writer.shutdown(Shutdown::Both);
+                        return;
+                    }
+                },
+                None => match inbound.events.recv() {
+                    Ok(event) => event,
+                    Err(_) => return,
+                },
+            };
+            match event {
+                Event::Data(sample) if sample.kind() == SampleKind::Put => {
+                    let written =
+                        (sample.payload().slices()).try_for_each(|slice| writer.write_all(slice));
+                    if written.is_err() {
+                        let _ = writer.shutdown(Shutdown::Both);
+                        return;
+                    }
+                }
+                Event::Data(_) => {
+                    let _ = writer.shutdown(Shutdown::Write);
+                    received = true;
+                }
+                Event::PeerLost(token) => lost = Some(token),
+                Event::Sent => sent = true,
+            }
+        }
+    });
+
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => p.put(&buf[..n]).wait().unwrap(),
+        }
+    }
+    p.delete().wait().unwrap();
+    let _ = local.send(Event::Sent);
+    receiving.join().unwrap();
+}
@@ -0,0 +1,307 @@
+use std::io::{self, BufRead, Read, Write};
+
+/********************/
+/*     Framing      */
+/********************/
+/// How sample boundaries are represented on stdin and stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// No boundaries: stdin is read in chunks and samples are written back to back
+    #[default]
+    Raw,
+    /// Records terminated by a delimiter byte, e.g. lines
+    Delimited(u8),
+    /// Records prefixed by their length as a big-endian u32
+    U32Be,
+    /// Records prefixed by their length as an unsigned LEB128 varint
+    Varint,
+    /// Records encoded as netstrings, i.e. `<length>:<data>,`
+    Netstring,
+}
+
+impl Framing {
+    /// Read the next record of at most `max` bytes, returns `None` on EOF.
+    ///
+    /// Raw chunks and delimited records longer than `max` are split,
+    /// length-prefixed records longer than `max` are an error.
+    pub(crate) fn read<R: BufRead>(
+        &self,
+        reader: &mut R,
+        max: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![];
+        match self {
+            Framing::Raw => {
+                buf.resize(max, 0);
+                let len = reader.read(&mut buf)?;
+                buf.truncate(len);
+            }
+            Framing::Delimited(delimiter) => {
+                reader.take(max as u64).read_until(*delimiter, &mut buf)?;
+            }
+            Framing::U32Be | Framing::Varint | Framing::Netstring => {
+                if reader.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                let len = match self {
+                    Framing::U32Be => {
+                        let mut len = [0u8; 4];
+                        reader.read_exact(&mut len)?;
+                        u32::from_be_bytes(len) as u64
+                    }
+                    Framing::Varint => read_varint(reader)?,
+                    _ => read_netstring_len(reader)?,
+                };
+                if len > max as u64 {
+                    return Err(invalid(format!(
+                        "record of {len} bytes exceeds the buffer size of {max} bytes"
+                    )));
+                }
+                buf.resize(len as usize, 0);
+                reader.read_exact(&mut buf)?;
+                if *self == Framing::Netstring {
+                    let mut comma = [0u8; 1];
+                    reader.read_exact(&mut comma)?;
+                    if comma[0] != b',' {
+                        return Err(invalid("netstring not terminated by ','".to_string()));
+                    }
+                }
+                return Ok(Some(buf));
+            }
+        }
+        Ok((!buf.is_empty()).then_some(buf))
+    }
+
+    /// Write `payload` as a single record.
+    pub(crate) fn write<'a, W, I>(&self, writer: &mut W, payload: I, len: usize) -> io::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        match self {
+            Framing::Raw | Framing::Delimited(_) => {}
+            Framing::U32Be => {
+                let len = u32::try_from(len)
+                    .map_err(|_| invalid(format!("record of {len} bytes is too large")))?;
+                writer.write_all(&len.to_be_bytes())?;
+            }
+            Framing::Varint => write_varint(writer, len as u64)?,
+            Framing::Netstring => write!(writer, "{len}:")?,
+        }
+        let mut last = None;
+        for slice in payload {
+            writer.write_all(slice)?;
+            last = slice.last().copied().or(last);
+        }
+        match self {
+            Framing::Delimited(delimiter) if last != Some(*delimiter) => {
+                writer.write_all(&[*delimiter])
+            }
+            Framing::Netstring => writer.write_all(b","),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse a delimiter byte, either a single character, an escape like `\0`, or hex like `0x1e`
+pub(crate) fn parse_byte(s: &str) -> Result<u8, String> {
+    match s {
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        "\\t" => Ok(b'\t'),
+        "\\0" => Ok(b'\0'),
+        _ => match s.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16).map_err(|e| e.to_string()),
+            None if s.len() == 1 => Ok(s.as_bytes()[0]),
+            None => Err(format!("expected a single byte, got `{s}`")),
+        },
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        // The 10th byte only carries the most significant bit
+        if shift == 63 && byte[0] & 0x7e != 0 {
+            return Err(invalid("varint overflows a u64".to_string()));
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long".to_string()))
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_netstring_len<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut len = 0u64;
+    // A u64 has at most 20 decimal digits
+    for _ in 0..=20 {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'0'..=b'9' => {
+                len = len
+                    .checked_mul(10)
+                    .and_then(|len| len.checked_add((byte[0] - b'0') as u64))
+                    .ok_or_else(|| invalid("netstring length is too large".to_string()))?;
+            }
+            b':' => return Ok(len),
+            b => {
+                return Err(invalid(format!(
+                    "unexpected byte {b:#04x} in netstring length"
+                )))
+            }
+        }
+    }
+    Err(invalid("netstring length is too large".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    const FRAMINGS: [Framing; 5] = [
+        Framing::Raw,
+        Framing::Delimited(b'\n'),
+        Framing::U32Be,
+        Framing::Varint,
+        Framing::Netstring,
+    ];
+
+    fn encode(framing: Framing, records: &[&[u8]]) -> Vec<u8> {
+        let mut buf = vec![];
+        for record in records {
+            framing.write(&mut buf, [*record], record.len()).unwrap();
+        }
+        buf
+    }
+
+    fn decode(framing: Framing, mut bytes: &[u8], max: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut records = vec![];
+        while let Some(record) = framing.read(&mut bytes, max)? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn round_trip() {
+        let records: [&[u8]; 3] = [b"hello\n", b"", b"\x00\xff,:world\n"];
+        for framing in FRAMINGS {
+            let bytes = encode(framing, &records);
+            let decoded = decode(framing, &bytes, 1024).unwrap();
+            match framing {
+                // Boundaries are lost
+                Framing::Raw => assert_eq!(decoded, [records.concat()]),
+                // Empty records cannot be represented
+                Framing::Delimited(_) => {
+                    assert_eq!(decoded, [&b"hello\n"[..], b"\n", b"\x00\xff,:world\n"])
+                }
+                _ => assert_eq!(decoded, records, "{framing:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn delimiter_is_appended() {
+        let bytes = encode(Framing::Delimited(0), &[b"a", b"b\0"]);
+        assert_eq!(bytes, b"a\0b\0");
+    }
+
+    #[test]
+    fn split_records() {
+        let bytes = b"abcdef\n";
+        assert_eq!(
+            decode(Framing::Delimited(b'\n'), bytes, 4).unwrap(),
+            [&b"abcd"[..], b"ef\n"]
+        );
+        assert_eq!(
+            decode(Framing::Raw, bytes, 4).unwrap(),
+            [&b"abcd"[..], b"ef\n"]
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        for framing in [Framing::U32Be, Framing::Varint, Framing::Netstring] {
+            let bytes = encode(framing, &[b"hello"]);
+            for len in 1..bytes.len() {
+                let err = decode(framing, &bytes[..len], 1024).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{framing:?} {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn oversized() {
+        for framing in [Framing::U32Be, Framing::Varint, Framing::Netstring] {
+            let bytes = encode(framing, &[b"hello"]);
+            assert!(decode(framing, &bytes, 5).is_ok());
+            let err = decode(framing, &bytes, 4).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{framing:?}");
+        }
+    }
+
+    #[test]
+    fn netstring_terminator() {
+        let err = decode(Framing::Netstring, b"5:hello;", 1024).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode(Framing::Netstring, b"5x:hello,", 1024).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            write_varint(&mut buf, value).unwrap();
+            assert_eq!(read_varint(&mut buf.as_slice()).unwrap(), value);
+        }
+        // u64::MAX takes 10 bytes
+        let mut buf = vec![];
+        write_varint(&mut buf, u64::MAX).unwrap();
+        assert_eq!(
+            buf,
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+        // A 10th byte beyond the most significant bit overflows
+        buf[9] = 0x02;
+        let err = read_varint(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // An 11th byte is too long
+        buf[9] = 0x80;
+        buf.push(0x00);
+        let err = read_varint(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn byte() {
+        assert_eq!(parse_byte("\\n"), Ok(b'\n'));
+        assert_eq!(parse_byte("\\0"), Ok(0));
+        assert_eq!(parse_byte("0x1e"), Ok(0x1e));
+        assert_eq!(parse_byte(","), Ok(b','));
+        assert!(parse_byte("0x100").is_err());
+        assert!(parse_byte("ab").is_err());
+        assert!(parse_byte("").is_err());
+    }
+}
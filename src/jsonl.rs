@@ -0,0 +1,156 @@
+use crate::attachment;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+use zenoh::{
+    bytes::{Encoding, ZBytes},
+    qos::{CongestionControl, Reliability},
+    sample::{Sample, SampleKind},
+    time::Timestamp,
+};
+
+/********************/
+/*   JSON Lines     */
+/********************/
+/// Convert a sample and its metadata into a JSON object.
+///
+/// Attachments serialized as maps are represented as JSON objects. Otherwise payload and
+/// attachment are represented as UTF-8 strings when possible, or as base64 strings in the
+/// `payload_base64` and `attachment_base64` fields.
+pub(crate) fn sample_to_json(sample: &Sample) -> Value {
+    let mut obj = Map::new();
+    obj.insert("key".into(), json!(sample.key_expr().as_str()));
+    obj.insert(
+        "kind".into(),
+        json!(match sample.kind() {
+            SampleKind::Put => "put",
+            SampleKind::Delete => "delete",
+        }),
+    );
+    insert_bytes(&mut obj, "payload", Some(sample.payload()));
+    obj.insert("encoding".into(), json!(sample.encoding().to_string()));
+    obj.insert(
+        "timestamp".into(),
+        json!(sample.timestamp().map(|t| t.to_string())),
+    );
+    obj.insert("priority".into(), json!(sample.priority() as u8));
+    obj.insert(
+        "congestion_control".into(),
+        json!(match sample.congestion_control() {
+            CongestionControl::Drop => "drop",
+            CongestionControl::Block => "block",
+            CongestionControl::BlockFirst => "block_first",
+        }),
+    );
+    obj.insert(
+        "reliability".into(),
+        json!(match sample.reliability() {
+            Reliability::Reliable => "reliable",
+            Reliability::BestEffort => "besteffort",
+        }),
+    );
+    obj.insert("express".into(), json!(sample.express()));
+    obj.insert(
+        "source_info".into(),
+        json!(sample.source_info().map(|info| json!({
+            "id": format!("{}:{}", info.source_id().zid(), info.source_id().eid()),
+            "sn": info.source_sn(),
+        }))),
+    );
+    match sample.attachment().and_then(attachment::deserialize) {
+        Some(attachment) => {
+            let map = attachment.into_iter().map(|(k, v)| (k, json!(v))).collect();
+            obj.insert("attachment".into(), Value::Object(map));
+        }
+        None => insert_bytes(&mut obj, "attachment", sample.attachment()),
+    }
+    Value::Object(obj)
+}
+
+fn insert_bytes(obj: &mut Map<String, Value>, field: &str, bytes: Option<&ZBytes>) {
+    let Some(bytes) = bytes else {
+        obj.insert(field.into(), Value::Null);
+        return;
+    };
+    match bytes.try_to_string() {
+        Ok(s) => obj.insert(field.into(), json!(s)),
+        Err(_) => obj.insert(
+            format!("{field}_base64"),
+            json!(BASE64_STANDARD.encode(bytes.to_bytes())),
+        ),
+    };
+}
+
+/// A record to publish, parsed from a JSON object.
+#[derive(Clone, Debug)]
+pub(crate) struct Record {
+    /// Absolute key expression overriding the publisher one
+    pub(crate) key: Option<String>,
+    /// Suffix appended to the publisher key expression
+    pub(crate) key_suffix: Option<String>,
+    pub(crate) kind: SampleKind,
+    pub(crate) payload: Vec<u8>,
+    pub(crate) encoding: Option<Encoding>,
+    pub(crate) attachment: Option<Vec<u8>>,
+    pub(crate) timestamp: Option<Timestamp>,
+}
+
+/// Parse a JSON object into a [`Record`], all the fields are optional.
+///
+/// The accepted fields mirror the ones produced by [`sample_to_json`],
+/// with the addition of `key_suffix`.
+pub(crate) fn record_from_json(line: &str) -> Result<Record, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let Value::Object(obj) = value else {
+        return Err("expected a JSON object".to_string());
+    };
+
+    let string = |field: &str| -> Result<Option<&str>, String> {
+        match obj.get(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(format!("`{field}` must be a string")),
+        }
+    };
+    let bytes = |field: &str| -> Result<Option<Vec<u8>>, String> {
+        if let Some(s) = string(field)? {
+            return Ok(Some(s.as_bytes().to_vec()));
+        }
+        let field = format!("{field}_base64");
+        match string(&field)? {
+            Some(s) => BASE64_STANDARD
+                .decode(s)
+                .map(Some)
+                .map_err(|e| format!("`{field}`: {e}")),
+            None => Ok(None),
+        }
+    };
+
+    Ok(Record {
+        key: string("key")?.map(str::to_string),
+        key_suffix: string("key_suffix")?.map(str::to_string),
+        kind: match string("kind")? {
+            None | Some("put") => SampleKind::Put,
+            Some("delete") => SampleKind::Delete,
+            Some(kind) => return Err(format!("unknown kind `{kind}`")),
+        },
+        payload: bytes("payload")?.unwrap_or_default(),
+        encoding: string("encoding")?.map(Encoding::from),
+        attachment: match obj.get("attachment") {
+            Some(Value::Object(map)) => {
+                let attachment = map
+                    .iter()
+                    .map(|(k, v)| match v {
+                        Value::String(v) => Ok((k.clone(), v.clone())),
+                        _ => Err(format!("`attachment`: value of `{k}` must be a string")),
+                    })
+                    .collect::<Result<_, _>>()?;
+                attachment::serialize(&attachment).map(|a| a.to_bytes().into_owned())
+            }
+            _ => bytes("attachment")?,
+        },
+        timestamp: string("timestamp")?
+            .map(|t| Timestamp::from_str(t).map_err(|e| format!("`timestamp`: {e:?}")))
+            .transpose()?,
+    })
+}
@@ -1,10 +1,57 @@
+mod attachment;
+mod checksum;
+mod compression;
+mod crypto;
+mod forward;
+mod framing;
+mod jsonl;
+mod signature;
+mod transfer;
+mod udp;
 mod utils;
 
+use attachment::Attachment;
+use checksum::Hasher;
 use clap::Parser;
-use std::io::{Read, Write};
-use utils::{CliArgs, Params, PubParams, SubParams};
-use zenoh::sample::SampleKind;
-use zenoh::{Config, Wait};
+use framing::Framing;
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc::RecvTimeoutError, Arc, Mutex};
+use std::time::{Duration, Instant};
+use utils::{
+    CliArgs, EofMarker, EofPolicy, Format, GetParams, Params, PipeParams, PubParams, ServeParams,
+    SubParams,
+};
+use zenoh::bytes::{Encoding, ZBytes};
+use zenoh::key_expr::KeyExpr;
+use zenoh::pubsub::Subscriber;
+use zenoh::qos::CongestionControl;
+use zenoh::query::Query;
+use zenoh::sample::{Sample, SampleKind, SourceInfo};
+use zenoh::session::EntityGlobalId;
+use zenoh::time::Timestamp;
+use zenoh::{Config, Session, Wait};
+use zenoh_ext::{
+    AdvancedPublisher, AdvancedPublisherBuilderExt, AdvancedSubscriber,
+    AdvancedSubscriberBuilderExt, CacheConfig, HistoryConfig, MissDetectionConfig,
+};
+
+/// The suffix of the control key expression EOF is signalled on
+const EOF_SUFFIX: &str = "@zat/eof";
+/// The number of samples buffered between zenoh and stdout
+const CHANNEL_SIZE: usize = 256;
+/// The suffix of the liveliness tokens declared by readers
+const READER_SUFFIX: &str = "@zat/reader";
+/// The suffix of the liveliness tokens declared by writers
+const WRITER_SUFFIX: &str = "@zat/writer";
+/// The period at which matching subscribers are checked while waiting for them
+const WAIT_PERIOD: Duration = Duration::from_millis(100);
+/// The exit status on timeout, same as the `timeout` command
+const EXIT_TIMEOUT: i32 = 124;
 
 fn main() {
     zenoh::try_init_log_from_env();
@@ -15,63 +62,1051 @@ fn main() {
 
     let s = zenoh::open(config).wait().unwrap();
 
-    // Read from zenoh and write to stdout
     match params {
-        Params::Read(SubParams {
-            keyexpr,
-            ignore_eof,
+        Params::Read(params) => {
+            let end = read(&s, params);
+            s.close().wait().unwrap();
+            if let Some(status) = end.status() {
+                std::process::exit(status);
+            }
+        }
+        Params::Write(params) => {
+            let p = declare_publisher(&s, &params);
+            let hasher = Mutex::new(params.checksum.map(Hasher::new));
+            write(&s, &p, &hasher, params);
+            s.close().wait().unwrap();
+        }
+        Params::Pipe(PipeParams {
+            publisher,
+            subscriber,
         }) => {
-            let sub = s.declare_subscriber(keyexpr).wait().unwrap();
+            // The read side keeps running after EOF on stdin until the peer's EOF
+            let marker = publisher.eof_marker;
+            let mut signer = (publisher.sign_key.as_ref())
+                .map(|path| signature::Signer::new(load_key(path, signature::load_signing_key)));
+            let mut sealer = (publisher.psk_file.as_ref())
+                .map(|path| crypto::Sealer::new(&load_key(path, crypto::load_key)));
+            let p = Arc::new(declare_publisher(&s, &publisher));
+            let hasher = Arc::new(Mutex::new(publisher.checksum.map(Hasher::new)));
+            let (session, pp, h) = (s.clone(), p.clone(), hasher.clone());
+            std::thread::spawn(move || write(&session, &pp, &h, publisher));
+            let end = read(&s, subscriber);
+            // Signal EOF to the peer in case stdin is still open
+            eof(&s, &p, marker, &hasher, sealer.as_mut(), signer.as_mut());
+            s.close().wait().unwrap();
+            if let Some(status) = end.status() {
+                std::process::exit(status);
+            }
+        }
+        Params::Get(GetParams {
+            selector,
+            target,
+            consolidation,
+            timeout,
+        }) => {
+            let mut get = s.get(selector).target(target).consolidation(consolidation);
+            if let Some(timeout) = timeout {
+                get = get.timeout(timeout);
+            }
+            let replies = get.wait().unwrap();
 
-            while let Ok(sample) = sub.recv() {
-                match sample.kind() {
-                    SampleKind::Put => {
+            while let Ok(reply) = replies.recv() {
+                match reply.result() {
+                    Ok(sample) => {
                         let mut stdout = std::io::stdout().lock();
                         for slice in sample.payload().slices() {
                             stdout.write_all(slice).unwrap();
                         }
                     }
-                    SampleKind::Delete => {
-                        if !ignore_eof {
-                            s.close().wait().unwrap();
-                            break;
-                        }
+                    Err(err) => {
+                        eprintln!("{}", String::from_utf8_lossy(&err.payload().to_bytes()));
                     }
                 }
             }
+            s.close().wait().unwrap();
         }
-        Params::Write(PubParams {
+        Params::Serve(ServeParams {
             keyexpr,
-            reliability,
-            congestion_control,
-            priority,
-            express,
-            buffer,
+            complete,
+            exec,
         }) => {
-            let p = s
-                .declare_publisher(keyexpr)
-                .reliability(reliability)
-                .congestion_control(congestion_control)
-                .priority(priority)
-                .express(express)
+            let payload = match exec {
+                Some(_) => ZBytes::default(),
+                None => {
+                    let mut buf = vec![];
+                    std::io::stdin().lock().read_to_end(&mut buf).unwrap();
+                    ZBytes::from(buf)
+                }
+            };
+
+            let q = s
+                .declare_queryable(&keyexpr)
+                .complete(complete)
                 .wait()
                 .unwrap();
 
-            let mut stdin = std::io::stdin().lock();
-            let mut buf = vec![0u8; buffer];
-            while let Ok(len) = stdin.read(&mut buf) {
-                buf.truncate(len);
+            while let Ok(query) = q.recv() {
+                match exec.as_ref() {
+                    Some(command) => {
+                        let command = command.clone();
+                        let keyexpr = keyexpr.clone();
+                        std::thread::spawn(move || match run(&command, &query) {
+                            Ok(stdout) => query.reply(&keyexpr, stdout).wait().unwrap(),
+                            Err(stderr) => query.reply_err(stderr).wait().unwrap(),
+                        });
+                    }
+                    None => query.reply(&keyexpr, payload.clone()).wait().unwrap(),
+                }
+            }
+        }
+        Params::Send(params) => {
+            transfer::send(&s, params);
+            s.close().wait().unwrap();
+        }
+        Params::Recv(params) => {
+            transfer::recv(&s, params);
+            s.close().wait().unwrap();
+        }
+        Params::Forward(params) => {
+            forward::forward(&s, params);
+            s.close().wait().unwrap();
+        }
+        Params::Udp(params) => {
+            udp::bridge(&s, params);
+            s.close().wait().unwrap();
+        }
+    }
+}
+
+/// Why reading from zenoh ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadEnd {
+    /// EOF according to the EOF policy
+    Eof,
+    /// The expected number of samples was received
+    Count,
+    /// The overall or idle timeout expired
+    Timeout,
+    /// A sample was missed in strict mode
+    Miss,
+    /// The checksum of a writer did not match
+    Mismatch,
+    /// A sample could not be decompressed
+    Corrupt,
+}
+
+impl ReadEnd {
+    /// The exit status when reading did not succeed
+    fn status(self) -> Option<i32> {
+        match self {
+            ReadEnd::Eof | ReadEnd::Count => None,
+            ReadEnd::Timeout => Some(EXIT_TIMEOUT),
+            ReadEnd::Miss | ReadEnd::Mismatch | ReadEnd::Corrupt => Some(-1),
+        }
+    }
+}
+
+/// Read from zenoh and write to stdout until EOF
+fn read(s: &Session, params: SubParams) -> ReadEnd {
+    let SubParams {
+        keyexpr,
+        eof_policy,
+        accept_encoding,
+        print_encoding,
+        print_attachment,
+        count,
+        timeout,
+        idle_timeout,
+        history,
+        strict,
+        framing,
+        eof_marker,
+        format,
+        checksum,
+        psk_file,
+        verify_key,
+        max_age,
+    } = params;
+    let mut verifier = verify_key.map(|path| {
+        signature::Verifier::new(load_key(&path, signature::load_verifying_key), max_age)
+    });
+    let mut opener = psk_file.map(|path| crypto::Opener::new(&load_key(&path, crypto::load_key)));
+
+    // Data samples, EOF control samples and writer tokens are received on the same channel
+    let (tx, rx) = std::sync::mpsc::sync_channel::<Sample>(CHANNEL_SIZE);
+    let (tx_eof, tx_writer) = (tx.clone(), tx.clone());
+    let callback = move |sample| {
+        let _ = tx.send(sample);
+    };
+    let sub = match history {
+        // The advanced subscriber fetches the history of publisher caches,
+        // including the ones of publishers discovered later on
+        true => DataSubscriber::Advanced(
+            s.declare_subscriber(&keyexpr)
+                .callback(callback)
+                .history(HistoryConfig::default().detect_late_publishers())
+                .wait()
+                .unwrap(),
+        ),
+        false => DataSubscriber::Plain(
+            s.declare_subscriber(&keyexpr)
+                .callback(callback)
+                .wait()
+                .unwrap(),
+        ),
+    };
+    let _eof = s
+        .declare_subscriber(eof_keyexpr(&keyexpr))
+        .callback(move |sample| {
+            let _ = tx_eof.send(sample);
+        })
+        .wait()
+        .unwrap();
+    // Writers are known before their first sample, including the ones declared earlier
+    let _writers = s
+        .liveliness()
+        .declare_subscriber(keyexpr.join(&format!("{WRITER_SUFFIX}/*/*")).unwrap())
+        .history(true)
+        .callback(move |sample| {
+            let _ = tx_writer.send(sample);
+        })
+        .wait()
+        .unwrap();
+    // Let writers count the matching readers
+    let _token = s
+        .liveliness()
+        .declare_token(reader_keyexpr(&keyexpr, &sub.id()))
+        .wait()
+        .unwrap();
+
+    // Samples from storages are emitted first, the ones also received live are skipped
+    let mut stored = match history {
+        true => query_storages(s, &keyexpr),
+        false => VecDeque::new(),
+    };
+    let mut seen: HashSet<(String, SampleId)> = stored.iter().filter_map(sample_id).collect();
+
+    // Whether each known writer reached EOF, by display id as writers are also known by
+    // their token, writers without source info are tracked as one
+    let mut writers: HashMap<Option<String>, bool> = HashMap::new();
+    // The last sequence number received from each writer on each key expression
+    let mut sequences: HashMap<(EntityGlobalId, String), u32> = HashMap::new();
+    // The checksum of the payloads received from each writer
+    let mut hashers: HashMap<Option<EntityGlobalId>, Hasher> = HashMap::new();
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut received = 0;
+
+    loop {
+        let idle_deadline = idle_timeout.map(|t| Instant::now() + t);
+        let mut sample = if let Some(sample) = stored.pop_front() {
+            sample
+        } else {
+            let sample = match deadline.into_iter().chain(idle_deadline).min() {
+                Some(at) => match rx.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                    Ok(sample) => sample,
+                    Err(RecvTimeoutError::Timeout) => return ReadEnd::Timeout,
+                    Err(RecvTimeoutError::Disconnected) => return ReadEnd::Eof,
+                },
+                None => match rx.recv() {
+                    Ok(sample) => sample,
+                    Err(_) => return ReadEnd::Eof,
+                },
+            };
+            if sample_id(&sample).is_some_and(|id| seen.remove(&id)) {
+                continue;
+            }
+            sample
+        };
+        if let Some(writer) = token_writer(sample.key_expr()) {
+            if sample.kind() == SampleKind::Put {
+                writers.entry(Some(writer)).or_insert(false);
+            }
+            continue;
+        }
+
+        let control = is_eof_keyexpr(sample.key_expr());
+        // Signatures cover the payloads as sent, i.e. encrypted and compressed
+        if let Some(verifier) = verifier.as_mut() {
+            let keyexpr = sample.key_expr().clone();
+            sample = match verifier.verify(sample) {
+                Ok(sample) => sample,
+                Err(e) => {
+                    eprintln!("Dropping a sample on {keyexpr}: {e}");
+                    continue;
+                }
+            };
+        }
+        if let Some(opener) = opener.as_mut() {
+            let keyexpr = sample.key_expr().clone();
+            // Samples without payload carry a sealed payload in their attachment
+            let opened = match !control && sample.kind() == SampleKind::Put {
+                true => opener.open(sample),
+                false => opener.open_attachment(sample),
+            };
+            sample = match opened {
+                Ok(sample) => sample,
+                Err(e) => {
+                    eprintln!("Dropping a sample on {keyexpr}: {e}");
+                    continue;
+                }
+            };
+        }
+        let sample = match compression::decompress_sample(sample) {
+            Ok(sample) => sample,
+            Err(e) => {
+                eprintln!("Failed to decompress a sample on {e}");
+                return ReadEnd::Corrupt;
+            }
+        };
+        let source = sample.source_info().map(|info| *info.source_id());
+        let writer = source.as_ref().map(display_id);
+
+        if !control {
+            if let Some(info) = sample.source_info() {
+                let writer = (*info.source_id(), sample.key_expr().to_string());
+                match check_sequence(&mut sequences, writer, info.source_sn()) {
+                    Sequence::Next => {}
+                    Sequence::Gap(missed) => {
+                        eprintln!(
+                            "Missed {missed} sample(s) from {}",
+                            display_id(info.source_id())
+                        );
+                        if strict {
+                            return ReadEnd::Miss;
+                        }
+                    }
+                    Sequence::Late => {
+                        eprintln!("Out of order sample from {}", display_id(info.source_id()));
+                    }
+                    Sequence::Duplicate => {
+                        eprintln!("Duplicate sample from {}", display_id(info.source_id()));
+                        continue;
+                    }
+                }
+            }
+
+            if sample.kind() == SampleKind::Put {
+                writers.entry(writer.clone()).or_insert(false);
+                if let Some(checksum) = checksum {
+                    let hasher = hashers
+                        .entry(source)
+                        .or_insert_with(|| Hasher::new(checksum));
+                    for slice in sample.payload().slices() {
+                        hasher.update(slice);
+                    }
+                }
+
+                let encoding = sample.encoding().to_string();
+                // An accepted encoding without schema matches any schema, e.g. "text/plain;charset=utf-8"
+                let accepted = accept_encoding.is_empty()
+                    || accept_encoding.iter().any(|a| {
+                        *a == encoding
+                            || (!a.contains(';') && encoding.split(';').next() == Some(a))
+                    });
+                if !accepted {
+                    continue;
+                }
+                if print_encoding {
+                    eprintln!("{}: {encoding}", sample.key_expr());
+                }
+            }
+            if let Some(a) = sample.attachment().filter(|_| print_attachment) {
+                eprintln!("{}: {}", sample.key_expr(), attachment::display(a));
+            }
+
+            let mut stdout = std::io::stdout().lock();
+            if format == Format::Jsonl {
+                writeln!(stdout, "{}", jsonl::sample_to_json(&sample)).unwrap();
+            } else if sample.kind() == SampleKind::Put {
+                let payload = sample.payload();
+                framing
+                    .write(&mut stdout, payload.slices(), payload.len())
+                    .unwrap();
+            }
+            // Flush record by record, stdout is only line-buffered
+            stdout.flush().unwrap();
+
+            if sample.kind() == SampleKind::Put {
+                received += 1;
+                if count.is_some_and(|count| received >= count) {
+                    return ReadEnd::Count;
+                }
+            }
+        }
+
+        if control || (sample.kind() == SampleKind::Delete && eof_marker == EofMarker::Delete) {
+            // Only the first EOF of a writer is verified, e.g. `zat -b` may signal EOF twice
+            if let Some(checksum) = checksum.filter(|_| writers.get(&writer) != Some(&true)) {
+                let expected = sample
+                    .attachment()
+                    .and_then(attachment::deserialize)
+                    .and_then(|a| a.into_iter().find(|(k, _)| k == checksum::ATTACHMENT_KEY))
+                    .map(|(_, v)| v);
+                let actual = hashers
+                    .remove(&source)
+                    .unwrap_or_else(|| Hasher::new(checksum))
+                    .value();
+                let writer = writer.as_deref().unwrap_or("unknown writer");
+                match expected {
+                    Some(expected) if expected == actual => {}
+                    Some(expected) => {
+                        eprintln!(
+                            "Checksum mismatch from {writer}: expected {expected}, got {actual}"
+                        );
+                        return ReadEnd::Mismatch;
+                    }
+                    None => {
+                        eprintln!("No checksum received from {writer}");
+                        return ReadEnd::Mismatch;
+                    }
+                }
+            }
+            writers.insert(writer, true);
+            let eof = match eof_policy {
+                EofPolicy::First => true,
+                EofPolicy::All => writers.values().all(|eof| *eof),
+                EofPolicy::Never => false,
+            };
+            if eof {
+                return ReadEnd::Eof;
+            }
+        }
+    }
+}
+
+/// How a sample is sequenced relatively to the previous one of the same writer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sequence {
+    /// The next sample, or the first one of the writer
+    Next,
+    /// The given number of samples were missed before this one
+    Gap(u32),
+    /// A sample older than the previous one
+    Late,
+    /// The same sample as the previous one
+    Duplicate,
+}
+
+/// Check the sequence number `sn` of a sample against the last one of its `writer`.
+///
+/// Sequence numbers wrap around, the ones up to half the range ahead are considered newer.
+fn check_sequence<W: Eq + Hash>(sequences: &mut HashMap<W, u32>, writer: W, sn: u32) -> Sequence {
+    let last = match sequences.entry(writer) {
+        Entry::Occupied(last) => last.into_mut(),
+        Entry::Vacant(entry) => {
+            entry.insert(sn);
+            return Sequence::Next;
+        }
+    };
+    let sequence = match sn.wrapping_sub(*last) {
+        0 => Sequence::Duplicate,
+        1 => Sequence::Next,
+        d if d <= u32::MAX / 2 => Sequence::Gap(d - 1),
+        // Keep tracking from the newest sample
+        _ => return Sequence::Late,
+    };
+    *last = sn;
+    sequence
+}
+
+/// Format an entity id as `zid:eid`, like the JSON Lines `source_info` field
+fn display_id(id: &EntityGlobalId) -> String {
+    format!("{}:{}", id.zid(), id.eid())
+}
+
+/// The subscriber receiving data samples
+enum DataSubscriber {
+    Plain(Subscriber<()>),
+    Advanced(AdvancedSubscriber<()>),
+}
+
+impl DataSubscriber {
+    fn id(&self) -> EntityGlobalId {
+        match self {
+            DataSubscriber::Plain(sub) => sub.id(),
+            DataSubscriber::Advanced(sub) => sub.id(),
+        }
+    }
+}
+
+/// Query the samples stored on `keyexpr`, ordered by timestamp
+fn query_storages(s: &Session, keyexpr: &KeyExpr) -> VecDeque<Sample> {
+    let replies = s.get(keyexpr).wait().unwrap();
+    let mut samples: Vec<Sample> = replies
+        .iter()
+        .filter_map(|reply| reply.into_result().ok())
+        .collect();
+    samples.sort_by(|a, b| a.timestamp().cmp(&b.timestamp()));
+    samples.into()
+}
+
+/// What identifies a sample on its key expression
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum SampleId {
+    Timestamp(Timestamp),
+    /// The writer and sequence number, for samples without timestamp
+    Source(EntityGlobalId, u32),
+}
+
+/// Identify a sample received both from storages and live by its key and timestamp,
+/// or by its source info without timestamp
+fn sample_id(sample: &Sample) -> Option<(String, SampleId)> {
+    let id = match (sample.timestamp(), sample.source_info()) {
+        (Some(timestamp), _) => SampleId::Timestamp(*timestamp),
+        (None, Some(info)) => SampleId::Source(*info.source_id(), info.source_sn()),
+        (None, None) => return None,
+    };
+    Some((sample.key_expr().to_string(), id))
+}
+
+/// The control key expression EOF is signalled on when using [`EofMarker::Control`].
+///
+/// Verbatim chunks like `@zat` are not matched by wildcards, so storages and subscribers
+/// on e.g. `foo/**` never receive the EOF marker of `foo/bar`.
+fn eof_keyexpr(keyexpr: &KeyExpr) -> KeyExpr<'static> {
+    keyexpr.join(EOF_SUFFIX).unwrap()
+}
+
+fn is_eof_keyexpr(keyexpr: &KeyExpr) -> bool {
+    keyexpr
+        .as_str()
+        .strip_suffix(EOF_SUFFIX)
+        .is_some_and(|k| k.ends_with('/'))
+}
+
+/// The liveliness token key expression declared by the reader `id` on `keyexpr`
+fn reader_keyexpr(keyexpr: &KeyExpr, id: &EntityGlobalId) -> KeyExpr<'static> {
+    keyexpr
+        .join(&format!("{READER_SUFFIX}/{}/{}", id.zid(), id.eid()))
+        .unwrap()
+}
+
+/// The liveliness token key expression declared by the writer `id` on `keyexpr`
+fn writer_keyexpr(keyexpr: &KeyExpr, id: &EntityGlobalId) -> KeyExpr<'static> {
+    keyexpr
+        .join(&format!("{WRITER_SUFFIX}/{}/{}", id.zid(), id.eid()))
+        .unwrap()
+}
+
+/// The display id of the writer that declared the liveliness token `keyexpr`, if any
+fn token_writer(keyexpr: &KeyExpr) -> Option<String> {
+    let (_, id) = keyexpr
+        .as_str()
+        .rsplit_once(&format!("/{WRITER_SUFFIX}/"))?;
+    let (zid, eid) = id.split_once('/')?;
+    Some(format!("{zid}:{eid}"))
+}
+
+/// Wait until at least `n` subscribers match `p`, returns false on timeout.
+///
+/// Matching status only tells whether there is any subscriber, so beyond one
+/// the readers are counted by their liveliness tokens.
+fn wait_subscribers(
+    s: &Session,
+    p: &AdvancedPublisher,
+    n: usize,
+    timeout: Option<Duration>,
+) -> bool {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let readers = p.key_expr().join(&format!("{READER_SUFFIX}/**")).unwrap();
+    loop {
+        let matching = p.matching_status().wait().unwrap().matching() as usize;
+        let count = match n {
+            0 | 1 => matching,
+            _ => {
+                let replies = s.liveliness().get(&readers).wait().unwrap();
+                matching.max(replies.iter().filter(|r| r.result().is_ok()).count())
+            }
+        };
+        if count >= n {
+            return true;
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return false;
+        }
+        std::thread::sleep(WAIT_PERIOD);
+    }
+}
+
+/// Signal EOF on `p` according to `marker`, with the checksum of the published payloads
+fn eof(
+    s: &Session,
+    p: &AdvancedPublisher,
+    marker: EofMarker,
+    hasher: &Mutex<Option<Hasher>>,
+    sealer: Option<&mut crypto::Sealer>,
+    signer: Option<&mut signature::Signer>,
+) {
+    let checksum = hasher.lock().unwrap().as_ref().map(Hasher::value);
+    let entries: Attachment = (checksum.into_iter())
+        .map(|c| (checksum::ATTACHMENT_KEY.to_string(), c))
+        .collect();
+    let keyexpr = match marker {
+        EofMarker::Delete => p.key_expr().clone(),
+        EofMarker::Control => eof_keyexpr(p.key_expr()),
+    };
+    // The checksum is sealed as it tells about the plaintext,
+    // the attachment is a map so sealing and signing it cannot fail
+    let attachment = match sealer {
+        Some(sealer) => sealer
+            .seal_attachment(keyexpr.as_str(), None, &entries)
+            .unwrap(),
+        None => attachment::serialize(&entries),
+    };
+    let kind = match marker {
+        EofMarker::Delete => SampleKind::Delete,
+        EofMarker::Control => SampleKind::Put,
+    };
+    let attachment = match signer {
+        Some(signer) => signer
+            .sign(keyexpr.as_str(), kind, &p.id(), &[], attachment)
+            .unwrap(),
+        None => attachment,
+    };
+    match marker {
+        EofMarker::Delete => p.delete().attachment(attachment).wait().unwrap(),
+        // Stamp the marker with the publisher id for readers to track EOF per publisher
+        EofMarker::Control => s
+            .put(keyexpr, ZBytes::default())
+            .attachment(attachment)
+            .source_info(SourceInfo::new(p.id(), 0))
+            .priority(p.priority())
+            .congestion_control(CongestionControl::Block)
+            .wait()
+            .unwrap(),
+    }
+}
+
+/// Declare the publisher used to write to zenoh.
+///
+/// Sample miss detection stamps each sample with the publisher id and a sequence number,
+/// allowing readers to track EOF per publisher. With a cache, the publisher is detectable
+/// by advanced subscribers fetching history.
+fn declare_publisher(s: &Session, params: &PubParams) -> AdvancedPublisher<'static> {
+    let p = s
+        .declare_publisher(params.keyexpr.clone())
+        .encoding(params.encoding.clone().unwrap_or_default())
+        .reliability(params.reliability)
+        .congestion_control(params.congestion_control)
+        .priority(params.priority)
+        .express(params.express)
+        .advanced()
+        .sample_miss_detection(MissDetectionConfig::default());
+    match params.cache {
+        Some(n) => p
+            .cache(CacheConfig::default().max_samples(n))
+            .publisher_detection()
+            .wait(),
+        None => p.wait(),
+    }
+    .unwrap()
+}
+
+/// Read from stdin and write to zenoh on `p` until EOF, updating `hasher` with the payloads
+fn write(s: &Session, p: &AdvancedPublisher, hasher: &Mutex<Option<Hasher>>, params: PubParams) {
+    let PubParams {
+        keyexpr,
+        reliability,
+        congestion_control,
+        priority,
+        express,
+        buffer,
+        encoding,
+        attachment,
+        wait_subscribers: wait,
+        wait_timeout,
+        exit_on_unmatched,
+        cache: _,
+        compress,
+        framing,
+        eof_marker,
+        format,
+        checksum: _,
+        psk_file,
+        sign_key,
+    } = params;
+    let mut sealer = psk_file.map(|path| crypto::Sealer::new(&load_key(&path, crypto::load_key)));
+    let mut signer =
+        sign_key.map(|path| signature::Signer::new(load_key(&path, signature::load_signing_key)));
+    let attachment = attachment::serialize(&attachment);
+    // Let readers wait for the EOF of this writer before its first sample
+    let _token = s
+        .liveliness()
+        .declare_token(writer_keyexpr(&keyexpr, &p.id()))
+        .wait()
+        .unwrap();
+
+    if let Some(n) = wait {
+        if !wait_subscribers(s, p, n, wait_timeout) {
+            eprintln!("Timed out waiting for {n} matching subscriber(s)");
+            std::process::exit(EXIT_TIMEOUT);
+        }
+    }
+    if exit_on_unmatched {
+        let mut matched = false;
+        p.matching_listener()
+            .callback_mut(move |status| {
+                matched |= status.matching();
+                if matched && !status.matching() {
+                    eprintln!("No more matching subscribers");
+                    std::process::exit(-1);
+                }
+            })
+            .background()
+            .wait()
+            .unwrap();
+    }
+
+    let mut stdin = std::io::stdin().lock();
 
-                if buf.is_empty() {
+    if format == Format::Jsonl {
+        let mut line = String::new();
+        // The sequence numbers of the records published via the session, by key expression
+        let mut sequences: HashMap<String, u32> = HashMap::new();
+        for n in 1.. {
+            line.clear();
+            match stdin.read_line(&mut line) {
+                Ok(0) => {
                     drop(stdin);
-                    p.delete().wait().unwrap();
-                    s.close().wait().unwrap();
+                    eof(s, p, eof_marker, hasher, sealer.as_mut(), signer.as_mut());
                     break;
                 }
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to read from stdin: {e}");
+                    std::process::exit(-1);
+                }
+            }
+            let record = match jsonl::record_from_json(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    eprintln!("Invalid record on line {n}: {e}");
+                    std::process::exit(-1);
+                }
+            };
+
+            // Records on a different key expression are published via the session
+            let key = match (record.key, record.key_suffix) {
+                (None, None) => None,
+                (key, suffix) => {
+                    let key = key.map_or(Ok(keyexpr.clone()), KeyExpr::try_from);
+                    let key = match suffix {
+                        Some(suffix) => key.and_then(|k| k.join(&suffix)),
+                        None => key,
+                    };
+                    match key {
+                        Ok(key) => Some(key).filter(|key| key != p.key_expr()),
+                        Err(e) => {
+                            eprintln!("Invalid record on line {n}: {e}");
+                            std::process::exit(-1);
+                        }
+                    }
+                }
+            };
+            // Stamp them with the publisher id for readers to track EOF per publisher,
+            // readers track their sequence numbers per key expression
+            let source_info = key.as_ref().map(|key| {
+                let sn = sequences.entry(key.to_string()).or_insert(0);
+                let info = SourceInfo::new(p.id(), *sn);
+                *sn = sn.wrapping_add(1);
+                info
+            });
+            let encoding = record
+                .encoding
+                .or_else(|| encoding.clone())
+                .unwrap_or_else(|| detect_encoding(&record.payload));
+            let mut payload = record.payload;
+            let mut record_attachment = record
+                .attachment
+                .map(ZBytes::from)
+                .or_else(|| attachment.clone());
+            if record.kind == SampleKind::Put {
+                if let Some(hasher) = hasher.lock().unwrap().as_mut() {
+                    hasher.update(&payload);
+                }
+                if let Some(compress) = compress {
+                    payload = compression::compress(compress, &payload).unwrap();
+                    record_attachment = match compression::mark(record_attachment, compress) {
+                        Ok(attachment) => attachment,
+                        Err(e) => {
+                            eprintln!("Invalid record on line {n}: {e}");
+                            std::process::exit(-1);
+                        }
+                    };
+                }
+                if let Some(sealer) = sealer.as_mut() {
+                    let keyexpr = key.as_ref().unwrap_or(p.key_expr()).as_str();
+                    payload = match sealer.seal(keyexpr, &payload, record_attachment.as_ref()) {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            eprintln!("Invalid record on line {n}: {e}");
+                            std::process::exit(-1);
+                        }
+                    };
+                }
+            } else if let Some(sealer) = sealer.as_mut() {
+                let keyexpr = key.as_ref().unwrap_or(p.key_expr()).as_str();
+                record_attachment =
+                    match sealer.seal_attachment(keyexpr, record_attachment, &vec![]) {
+                        Ok(attachment) => attachment,
+                        Err(e) => {
+                            eprintln!("Invalid record on line {n}: {e}");
+                            std::process::exit(-1);
+                        }
+                    };
+            }
+            if let Some(signer) = signer.as_mut() {
+                let keyexpr = key.as_ref().unwrap_or(p.key_expr()).as_str();
+                record_attachment =
+                    match signer.sign(keyexpr, record.kind, &p.id(), &payload, record_attachment) {
+                        Ok(attachment) => attachment,
+                        Err(e) => {
+                            eprintln!("Invalid record on line {n}: {e}");
+                            std::process::exit(-1);
+                        }
+                    };
+            }
+            match (key, record.kind) {
+                // The advanced publisher timestamps samples itself if timestamping is enabled
+                (None, SampleKind::Put) => {
+                    let put = p.put(payload).encoding(encoding);
+                    match record.timestamp {
+                        Some(timestamp) => put.timestamp(timestamp),
+                        None => put,
+                    }
+                    .attachment(record_attachment)
+                    .wait()
+                }
+                (None, SampleKind::Delete) => {
+                    let delete = p.delete();
+                    match record.timestamp {
+                        Some(timestamp) => delete.timestamp(timestamp),
+                        None => delete,
+                    }
+                    .attachment(record_attachment)
+                    .wait()
+                }
+                (Some(key), SampleKind::Put) => s
+                    .put(key, payload)
+                    .encoding(encoding)
+                    .attachment(record_attachment)
+                    .timestamp(record.timestamp)
+                    .source_info(source_info)
+                    .reliability(reliability)
+                    .congestion_control(congestion_control)
+                    .priority(priority)
+                    .express(express)
+                    .wait(),
+                (Some(key), SampleKind::Delete) => s
+                    .delete(key)
+                    .attachment(record_attachment)
+                    .timestamp(record.timestamp)
+                    .source_info(source_info)
+                    .reliability(reliability)
+                    .congestion_control(congestion_control)
+                    .priority(priority)
+                    .express(express)
+                    .wait(),
+            }
+            .unwrap();
+        }
+        return;
+    }
+
+    // User attachments are maps, marking them cannot fail
+    let attachment = match compress {
+        Some(compress) => compression::mark(attachment, compress).unwrap(),
+        None => attachment,
+    };
+    let mut split = false;
+    loop {
+        let buf = match framing.read(&mut stdin, buffer) {
+            Ok(Some(buf)) => buf,
+            Ok(None) => {
+                drop(stdin);
+                eof(s, p, eof_marker, hasher, sealer.as_mut(), signer.as_mut());
+                break;
+            }
+            Err(e) => {
+                eprintln!("Failed to read from stdin: {e}");
+                std::process::exit(-1);
+            }
+        };
 
-                p.put(buf).wait().unwrap();
-                buf = vec![0u8; buffer];
+        if let Framing::Delimited(d) = framing {
+            if buf.len() == buffer && buf.last() != Some(&d) && !split {
+                eprintln!("Record exceeds the buffer size of {buffer} bytes: splitting it");
+                split = true;
             }
         }
+
+        if let Some(hasher) = hasher.lock().unwrap().as_mut() {
+            hasher.update(&buf);
+        }
+        let encoding = encoding.clone().unwrap_or_else(|| detect_encoding(&buf));
+        let buf = match compress {
+            Some(compress) => compression::compress(compress, &buf).unwrap(),
+            None => buf,
+        };
+        // User attachments are maps, sealing and signing them cannot fail
+        let buf = match sealer.as_mut() {
+            Some(sealer) => sealer
+                .seal(p.key_expr().as_str(), &buf, attachment.as_ref())
+                .unwrap(),
+            None => buf,
+        };
+        let attachment = match signer.as_mut() {
+            Some(signer) => signer
+                .sign(
+                    p.key_expr().as_str(),
+                    SampleKind::Put,
+                    &p.id(),
+                    &buf,
+                    attachment.clone(),
+                )
+                .unwrap(),
+            None => attachment.clone(),
+        };
+        p.put(buf)
+            .encoding(encoding)
+            .attachment(attachment)
+            .wait()
+            .unwrap();
+    }
+}
+
+/// Load a key from `path` with `load`, exit on error
+fn load_key<K, E: Display>(path: &Path, load: impl FnOnce(&Path) -> Result<K, E>) -> K {
+    match load(path) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Failed to load the key from {}: {e}", path.display());
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// Detect the encoding of `payload`: UTF-8 text or raw bytes
+fn detect_encoding(payload: &[u8]) -> Encoding {
+    match std::str::from_utf8(payload) {
+        Ok(_) => Encoding::TEXT_PLAIN,
+        Err(_) => Encoding::APPLICATION_OCTET_STREAM,
+    }
+}
+
+/// Run `command` in a shell for `query`: the query payload is written to its stdin and
+/// the selector is exposed via `ZAT_SELECTOR`, `ZAT_KEYEXPR` and `ZAT_PARAMETERS`.
+/// Returns the command stdout on success and its stderr otherwise.
+fn run(command: &str, query: &Query) -> Result<Vec<u8>, Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ZAT_SELECTOR", query.selector().to_string())
+        .env("ZAT_KEYEXPR", query.key_expr().as_str())
+        .env("ZAT_PARAMETERS", query.parameters().as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run `{command}`: {e}").into_bytes())?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = query.payload().map(|p| p.to_bytes().into_owned());
+    let writer = std::thread::spawn(move || {
+        if let Some(input) = input {
+            // The command may exit without consuming its stdin
+            let _ = stdin.write_all(&input);
+        }
+    });
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| e.to_string().into_bytes())?;
+    writer.join().unwrap();
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else if output.stderr.is_empty() {
+        Err(format!("`{command}` {}", output.status).into_bytes())
+    } else {
+        Err(output.stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence() {
+        let mut sequences = HashMap::new();
+        assert_eq!(check_sequence(&mut sequences, "a", 5), Sequence::Next);
+        assert_eq!(check_sequence(&mut sequences, "a", 6), Sequence::Next);
+        // Writers are tracked independently
+        assert_eq!(check_sequence(&mut sequences, "b", 0), Sequence::Next);
+        assert_eq!(check_sequence(&mut sequences, "a", 6), Sequence::Duplicate);
+        assert_eq!(check_sequence(&mut sequences, "a", 9), Sequence::Gap(2));
+        assert_eq!(check_sequence(&mut sequences, "a", 8), Sequence::Late);
+        // Late samples do not move the sequence backwards
+        assert_eq!(check_sequence(&mut sequences, "a", 10), Sequence::Next);
+        assert_eq!(check_sequence(&mut sequences, "b", 1), Sequence::Next);
+    }
+
+    #[test]
+    fn sequence_wrap_around() {
+        let mut sequences = HashMap::new();
+        assert_eq!(
+            check_sequence(&mut sequences, 0, u32::MAX - 1),
+            Sequence::Next
+        );
+        assert_eq!(check_sequence(&mut sequences, 0, u32::MAX), Sequence::Next);
+        assert_eq!(check_sequence(&mut sequences, 0, 0), Sequence::Next);
+        assert_eq!(check_sequence(&mut sequences, 0, u32::MAX), Sequence::Late);
+        assert_eq!(check_sequence(&mut sequences, 0, 3), Sequence::Gap(2));
+        assert_eq!(check_sequence(&mut sequences, 0, 3), Sequence::Duplicate);
+        // Up to half the range ahead is newer, beyond it is older
+        assert_eq!(
+            check_sequence(&mut sequences, 0, 3 + u32::MAX / 2),
+            Sequence::Gap(u32::MAX / 2 - 1)
+        );
+        assert_eq!(check_sequence(&mut sequences, 0, 2), Sequence::Late);
+    }
+
+    #[test]
+    fn sample_ids() {
+        use zenoh::sample::SampleBuilder;
+        use zenoh::time::{TimestampId, NTP64};
+
+        let keyexpr = KeyExpr::new("foo/bar").unwrap();
+        let writer = EntityGlobalId::default();
+        let put =
+            |sn| SampleBuilder::put(keyexpr.clone(), "x").source_info(SourceInfo::new(writer, sn));
+        let timestamp = Timestamp::new(NTP64(1), TimestampId::try_from([1u8; 16]).unwrap());
+        // The timestamp identifies a sample, regardless of its source info
+        assert_eq!(
+            sample_id(&put(0).timestamp(timestamp).into()),
+            sample_id(&put(1).timestamp(timestamp).into())
+        );
+        // The source info identifies a sample without timestamp
+        let untimed = sample_id(&put(0).into());
+        assert_eq!(
+            untimed,
+            Some(("foo/bar".to_string(), SampleId::Source(writer, 0)))
+        );
+        assert_ne!(untimed, sample_id(&put(1).into()));
+        assert_eq!(sample_id(&SampleBuilder::put(keyexpr, "x").into()), None);
+    }
+
+    #[test]
+    fn writer_token() {
+        let id = EntityGlobalId::default();
+        let keyexpr = KeyExpr::new("foo/bar").unwrap();
+        let token = writer_keyexpr(&keyexpr, &id);
+        assert_eq!(token_writer(&token), Some(display_id(&id)));
+        // Neither data nor reader tokens are writer tokens
+        assert_eq!(token_writer(&keyexpr), None);
+        assert_eq!(token_writer(&reader_keyexpr(&keyexpr, &id)), None);
     }
 }
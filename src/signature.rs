@@ -0,0 +1,319 @@
+use crate::attachment::{self, Attachment};
+use crate::crypto::{self, ReplayWindow, STREAM_ID_LEN};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zenoh::bytes::ZBytes;
+use zenoh::sample::{Sample, SampleKind};
+use zenoh::session::EntityGlobalId;
+use zenoh_ext::z_serialize;
+
+/********************/
+/*    Signature     */
+/********************/
+// Each signer numbers its samples with a u64 counter within a random 16-byte stream id, like
+// encrypted payloads, and stamps them with the signing time. The signature covers the key
+// expression, the kind, the writer id, the stream id, the counter, the signing time, the payload
+// and the other attachment entries, so that samples cannot be replayed, e.g. a signed EOF marker
+// to truncate a later stream, nor turned into deletes. The stream ids of a new reader are all
+// unknown: the signing time lets it reject the samples captured long before, see `max_age`.
+// The attachment entry is the stream id, the big-endian counter, the big-endian signing time
+// in milliseconds since the UNIX epoch and the signature.
+
+/// The attachment key of the base64 Ed25519 signature of a sample
+pub(crate) const ATTACHMENT_KEY: &str = "zat:signature";
+
+const COUNTER_LEN: usize = 8;
+const TIME_LEN: usize = 8;
+const HEADER_LEN: usize = STREAM_ID_LEN + COUNTER_LEN + TIME_LEN;
+const SIGNATURE_LEN: usize = 64;
+
+/// Load an Ed25519 private key from a PKCS#8 PEM file, e.g. `openssl genpkey -algorithm ed25519`
+pub(crate) fn load_signing_key(path: &Path) -> Result<SigningKey, String> {
+    let pem = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    SigningKey::from_pkcs8_pem(&pem).map_err(|e| e.to_string())
+}
+
+/// Load an Ed25519 public key from a PEM file, e.g. `openssl pkey -pubout`
+pub(crate) fn load_verifying_key(path: &Path) -> Result<VerifyingKey, String> {
+    let pem = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    VerifyingKey::from_public_key_pem(&pem).map_err(|e| e.to_string())
+}
+
+/// The current time in milliseconds since the UNIX epoch
+fn now() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_millis() as u64
+}
+
+/// The signed message, `header` being the stream id, the counter and the signing time
+fn message(
+    keyexpr: &str,
+    kind: SampleKind,
+    source: Option<&EntityGlobalId>,
+    header: &[u8],
+    payload: &[u8],
+    entries: &Attachment,
+) -> Vec<u8> {
+    let kind = match kind {
+        SampleKind::Put => 0u8,
+        SampleKind::Delete => 1u8,
+    };
+    let source = source.map_or(String::new(), |id| format!("{}:{}", id.zid(), id.eid()));
+    z_serialize(&(keyexpr, kind, source, header, payload, entries))
+        .to_bytes()
+        .into_owned()
+}
+
+/// Signs the samples of a writer.
+pub(crate) struct Signer {
+    key: SigningKey,
+    stream: [u8; STREAM_ID_LEN],
+    counter: u64,
+}
+
+impl Signer {
+    pub(crate) fn new(key: SigningKey) -> Self {
+        Signer {
+            key,
+            stream: crypto::stream_id(),
+            counter: 0,
+        }
+    }
+
+    /// Sign a sample of the writer `source` and add the signature to its `attachment`,
+    /// which must be a map.
+    pub(crate) fn sign(
+        &mut self,
+        keyexpr: &str,
+        kind: SampleKind,
+        source: &EntityGlobalId,
+        payload: &[u8],
+        attachment: Option<ZBytes>,
+    ) -> Result<Option<ZBytes>, String> {
+        let mut entries = attachment::entries(attachment.as_ref(), ATTACHMENT_KEY)?;
+        let counter = self.counter;
+        self.counter += 1;
+        let header = [
+            &self.stream[..],
+            &counter.to_be_bytes(),
+            &now().to_be_bytes(),
+        ]
+        .concat();
+        let message = message(keyexpr, kind, Some(source), &header, payload, &entries);
+        let signature = self.key.sign(&message);
+        let value = [&header[..], &signature.to_bytes()].concat();
+        entries.push((ATTACHMENT_KEY.to_string(), BASE64_STANDARD.encode(value)));
+        Ok(attachment::serialize(&entries))
+    }
+}
+
+/// Verifies the samples of all the signers, rejecting replayed ones.
+pub(crate) struct Verifier {
+    key: VerifyingKey,
+    /// The maximum difference between the signing time of a sample and the current time
+    max_age: Option<Duration>,
+    streams: ReplayWindow,
+}
+
+impl Verifier {
+    pub(crate) fn new(key: VerifyingKey, max_age: Option<Duration>) -> Self {
+        Verifier {
+            key,
+            max_age,
+            streams: ReplayWindow::default(),
+        }
+    }
+
+    /// Verify the signature of `sample`, which is then removed from the attachment.
+    pub(crate) fn verify(&mut self, sample: Sample) -> Result<Sample, String> {
+        let (value, entries) =
+            attachment::remove(&sample, ATTACHMENT_KEY).ok_or("missing signature")?;
+        let value = BASE64_STANDARD
+            .decode(value)
+            .ok()
+            .filter(|v| v.len() == HEADER_LEN + SIGNATURE_LEN)
+            .ok_or("invalid signature")?;
+        let (header, signature) = value.split_at(HEADER_LEN);
+        let (stream, rest) = header.split_at(STREAM_ID_LEN);
+        let (counter, time) = rest.split_at(COUNTER_LEN);
+        let stream: [u8; STREAM_ID_LEN] = stream.try_into().unwrap();
+        let counter = u64::from_be_bytes(counter.try_into().unwrap());
+        let time = u64::from_be_bytes(time.try_into().unwrap());
+        let signature = Signature::from_slice(signature).map_err(|_| "invalid signature")?;
+        if !self.streams.is_fresh(&stream, counter) {
+            return Err("out of order or replayed signature".to_string());
+        }
+        if (self.max_age).is_some_and(|max| now().abs_diff(time) > max.as_millis() as u64) {
+            return Err("stale signature".to_string());
+        }
+        let payload = sample.payload().to_bytes();
+        let message = message(
+            sample.key_expr().as_str(),
+            sample.kind(),
+            sample.source_info().map(|info| info.source_id()),
+            header,
+            &payload,
+            &entries,
+        );
+        (self.key.verify(&message, &signature)).map_err(|_| "signature verification failed")?;
+        // Only authenticated samples move the stream forward
+        self.streams.accept(stream, counter);
+        Ok(attachment::replace(sample, &entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::LazyLock;
+    use zenoh::key_expr::KeyExpr;
+    use zenoh::sample::{SampleBuilder, SourceInfo};
+
+    const SECRET: [u8; 32] = [7; 32];
+    /// The id of the writer, the default one is random
+    static WRITER: LazyLock<EntityGlobalId> = LazyLock::new(EntityGlobalId::default);
+
+    fn signer() -> Signer {
+        Signer::new(SigningKey::from_bytes(&SECRET))
+    }
+
+    fn verifier(max_age: Option<Duration>) -> Verifier {
+        Verifier::new(SigningKey::from_bytes(&SECRET).verifying_key(), max_age)
+    }
+
+    /// Sign a sample of the writer
+    fn sign(signer: &mut Signer, kind: SampleKind, payload: &[u8], entries: &Attachment) -> Sample {
+        let attachment = signer
+            .sign(
+                "foo/bar",
+                kind,
+                &WRITER,
+                payload,
+                attachment::serialize(entries),
+            )
+            .unwrap();
+        sample("foo/bar", kind, payload, attachment)
+    }
+
+    fn sample(
+        keyexpr: &'static str,
+        kind: SampleKind,
+        payload: &[u8],
+        attachment: Option<ZBytes>,
+    ) -> Sample {
+        let keyexpr = KeyExpr::new(keyexpr).unwrap();
+        let info = SourceInfo::new(*WRITER, 0);
+        match kind {
+            SampleKind::Put => SampleBuilder::put(keyexpr, payload.to_vec())
+                .attachment(attachment)
+                .source_info(info)
+                .into(),
+            SampleKind::Delete => SampleBuilder::delete(keyexpr)
+                .attachment(attachment)
+                .source_info(info)
+                .into(),
+        }
+    }
+
+    /// Rebuild `sample` as another kind, on another key expression or with another payload
+    fn forge(sample: &Sample, keyexpr: &'static str, kind: SampleKind, payload: &[u8]) -> Sample {
+        self::sample(keyexpr, kind, payload, sample.attachment().cloned())
+    }
+
+    fn entries(sample: &Sample) -> Attachment {
+        sample
+            .attachment()
+            .and_then(attachment::deserialize)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (mut signer, mut verifier) = (signer(), verifier(None));
+        let entries = vec![("a".to_string(), "b".to_string())];
+        let signed = sign(&mut signer, SampleKind::Put, b"hello", &entries);
+        let verified = verifier.verify(signed).unwrap();
+        assert_eq!(&*verified.payload().to_bytes(), b"hello");
+        assert_eq!(self::entries(&verified), entries);
+        let signed = sign(&mut signer, SampleKind::Delete, b"", &vec![]);
+        assert!(verifier.verify(signed).is_ok());
+    }
+
+    #[test]
+    fn wrong_key() {
+        let mut verifier = Verifier::new(SigningKey::from_bytes(&[8; 32]).verifying_key(), None);
+        let signed = sign(&mut signer(), SampleKind::Put, b"hello", &vec![]);
+        assert!(verifier.verify(signed).is_err());
+    }
+
+    #[test]
+    fn tampered() {
+        let mut signer = signer();
+        let signed = sign(&mut signer, SampleKind::Put, b"hello", &vec![]);
+        for forged in [
+            forge(&signed, "foo/baz", SampleKind::Put, b"hello"),
+            forge(&signed, "foo/bar", SampleKind::Put, b"hellO"),
+            // A signed put does not verify as a delete
+            forge(&signed, "foo/bar", SampleKind::Delete, b""),
+        ] {
+            assert!(verifier(None).verify(forged).is_err());
+        }
+        // Without the writer id
+        let unstamped = SampleBuilder::from(signed.clone())
+            .source_info(None::<SourceInfo>)
+            .into();
+        assert!(verifier(None).verify(unstamped).is_err());
+        // With another attachment
+        let mut entries = self::entries(&signed);
+        entries.insert(0, ("a".to_string(), "b".to_string()));
+        let altered = attachment::replace(signed, &entries);
+        assert!(verifier(None).verify(altered).is_err());
+    }
+
+    #[test]
+    fn replayed() {
+        let (mut signer, mut verifier) = (signer(), verifier(None));
+        let first = sign(&mut signer, SampleKind::Put, b"1", &vec![]);
+        let second = sign(&mut signer, SampleKind::Put, b"2", &vec![]);
+        let third = sign(&mut signer, SampleKind::Put, b"3", &vec![]);
+        assert!(verifier.verify(second.clone()).is_ok());
+        assert_eq!(
+            verifier.verify(second).unwrap_err(),
+            "out of order or replayed signature"
+        );
+        // Out of order
+        assert!(verifier.verify(first).is_err());
+        assert!(verifier.verify(third).is_ok());
+    }
+
+    #[test]
+    fn stale() {
+        let signed = sign(&mut signer(), SampleKind::Put, b"hello", &vec![]);
+        assert!(verifier(Some(Duration::from_secs(60)))
+            .verify(signed.clone())
+            .is_ok());
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            verifier(Some(Duration::ZERO)).verify(signed).unwrap_err(),
+            "stale signature"
+        );
+    }
+
+    #[test]
+    fn missing_signature() {
+        let mut verifier = verifier(None);
+        let unsigned = sample("foo/bar", SampleKind::Put, b"hello", None);
+        assert_eq!(verifier.verify(unsigned).unwrap_err(), "missing signature");
+
+        let signed = sign(&mut signer(), SampleKind::Put, b"hello", &vec![]);
+        let (value, mut entries) = attachment::remove(&signed, ATTACHMENT_KEY).unwrap();
+        let mut value = BASE64_STANDARD.decode(value).unwrap();
+        value.pop();
+        entries.push((ATTACHMENT_KEY.to_string(), BASE64_STANDARD.encode(value)));
+        let truncated = attachment::replace(signed, &entries);
+        assert_eq!(verifier.verify(truncated).unwrap_err(), "invalid signature");
+    }
+}
@@ -0,0 +1,434 @@
+use crate::utils::{RecvParams, SendParams};
+use crate::CHANNEL_SIZE;
+use sha2::{Digest, Sha256};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use zenoh::bytes::ZBytes;
+use zenoh::key_expr::KeyExpr;
+use zenoh::query::{ConsolidationMode, Query, QueryTarget};
+use zenoh::sample::Sample;
+use zenoh::{Session, Wait};
+use zenoh_ext::{z_deserialize, z_serialize};
+
+/********************/
+/*     Transfer     */
+/********************/
+// A sender publishes the chunks of stdin on `<keyexpr>/@zat/chunk/<offset>`, then the length
+// and the SHA-256 digest of stdin on `<keyexpr>/@zat/end`. The receiver acknowledges the chunks
+// it received by replying to queries on `<keyexpr>/@zat/ack/<zid>` with the ranges of bytes it has.
+// The sender retransmits the chunks which are not acknowledged in time, and does not send the
+// ones all the receivers already have, so that interrupted transfers are resumed. It tracks the
+// receivers by their key expression: chunks are kept until all of them acknowledged them, and
+// a receiver which does not reply is only considered gone after `RECEIVER_TIMEOUT`.
+
+/// The suffix of the key expressions chunks are published on
+const CHUNK_SUFFIX: &str = "@zat/chunk";
+/// The suffix of the key expression the end of the transfer is published on
+const END_SUFFIX: &str = "@zat/end";
+/// The suffix of the key expression receivers acknowledge chunks on
+const ACK_SUFFIX: &str = "@zat/ack";
+/// The maximum number of chunks sent and not acknowledged yet
+const SEND_WINDOW: usize = 256;
+/// The period at which acknowledgements are queried when no progress is made
+const ACK_PERIOD: Duration = Duration::from_millis(100);
+/// The timeout of acknowledgement queries
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// The delay after which a chunk which is not acknowledged is sent again
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long a receiver which is done waits for the sender to query its acknowledgement
+const LINGER: Duration = Duration::from_secs(1);
+/// How long a receiver which is not done may not reply before it is considered gone
+const RECEIVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The end of a transfer: length and SHA-256 digest
+type End = (u64, Vec<u8>);
+
+/// The acknowledgement of a receiver: whether it is done, the ranges of received bytes,
+/// and why the transfer failed, empty if it did not
+type Ack = (bool, Vec<(u64, u64)>, String);
+
+/// Whether the bytes from `start` to `end` are in one of `ranges`
+fn covered(ranges: &[(u64, u64)], start: u64, end: u64) -> bool {
+    ranges.iter().any(|(s, e)| *s <= start && end <= *e)
+}
+
+/// A receiver known by the sender
+struct Receiver {
+    /// The last acknowledgement of the receiver
+    ack: Ack,
+    /// When the last acknowledgement was received
+    seen: Instant,
+}
+
+/// The receivers known by the sender, by key expression
+type Receivers = HashMap<String, Receiver>;
+
+/// Whether all the receivers have the `len` bytes at `offset`
+fn acknowledged(receivers: &Receivers, offset: u64, len: usize) -> bool {
+    !receivers.is_empty()
+        && (receivers.values()).all(|r| covered(&r.ack.1, offset, offset + len as u64))
+}
+
+/// Update the known receivers with their `acks`, `kept` being the offset of the first chunk
+/// still kept. Exit if a known receiver lost acknowledged chunks.
+fn track(
+    receivers: &mut Receivers,
+    ignored: &mut HashSet<String>,
+    acks: Vec<(String, Ack)>,
+    kept: u64,
+) {
+    for (receiver, ack) in acks {
+        // Acknowledged chunks are not kept, a receiver missing some of them cannot be resumed
+        let complete = covered(&ack.1, 0, kept);
+        match receivers.entry(receiver) {
+            Entry::Occupied(_) if !complete => {
+                eprintln!("A receiver lost acknowledged chunks, restart the transfer to resume it");
+                std::process::exit(-1);
+            }
+            Entry::Occupied(mut entry) => {
+                *entry.get_mut() = Receiver {
+                    ack,
+                    seen: Instant::now(),
+                }
+            }
+            Entry::Vacant(entry) if complete => {
+                entry.insert(Receiver {
+                    ack,
+                    seen: Instant::now(),
+                });
+            }
+            Entry::Vacant(entry) => {
+                if ignored.insert(entry.key().clone()) {
+                    eprintln!(
+                        "Ignoring the receiver on {} which joined after chunks were acknowledged, \
+                        restart the transfer to resume it",
+                        entry.key()
+                    );
+                }
+            }
+        }
+    }
+    receivers.retain(|receiver, r| {
+        let gone = !r.ack.0 && r.seen.elapsed() >= RECEIVER_TIMEOUT;
+        if gone {
+            eprintln!("The receiver on {receiver} went away");
+        }
+        !gone
+    });
+}
+
+/// Read stdin in chunks and send them until all the receivers acknowledged them
+pub(crate) fn send(s: &Session, params: SendParams) {
+    let SendParams {
+        keyexpr,
+        reliability,
+        congestion_control,
+        priority,
+        express,
+        chunk_size,
+    } = params;
+    let chunk_keyexpr = keyexpr.join(CHUNK_SUFFIX).unwrap();
+    let end_keyexpr = keyexpr.join(END_SUFFIX).unwrap();
+    let ack_keyexpr = keyexpr.join(ACK_SUFFIX).unwrap().join("*").unwrap();
+    let put = |keyexpr: &KeyExpr, payload: ZBytes| {
+        s.put(keyexpr, payload)
+            .reliability(reliability)
+            .congestion_control(congestion_control)
+            .priority(priority)
+            .express(express)
+            .wait()
+            .unwrap()
+    };
+    let send_chunk = |offset: u64, chunk: &[u8]| {
+        let keyexpr = chunk_keyexpr.join(&offset.to_string()).unwrap();
+        put(&keyexpr, ZBytes::from(chunk));
+    };
+
+    let mut stdin = std::io::stdin().lock();
+    let mut hasher = Sha256::new();
+    // The chunks sent and not acknowledged yet by offset, with the instant they were last sent
+    let mut unacked: BTreeMap<u64, (Vec<u8>, Instant)> = BTreeMap::new();
+    let mut offset = 0u64;
+    // The end of the transfer once stdin reached EOF, with the instant it was last sent
+    let mut end: Option<(ZBytes, Instant)> = None;
+    // Receivers resuming a transfer already have some of the chunks
+    let mut receivers = Receivers::new();
+    // The receivers which cannot be resumed
+    let mut ignored = HashSet::new();
+    track(&mut receivers, &mut ignored, query_acks(s, &ack_keyexpr), 0);
+
+    loop {
+        while end.is_none() && unacked.len() < SEND_WINDOW {
+            let mut chunk = vec![];
+            if let Err(e) = (&mut stdin).take(chunk_size as u64).read_to_end(&mut chunk) {
+                eprintln!("Failed to read from stdin: {e}");
+                std::process::exit(-1);
+            }
+            if chunk.is_empty() {
+                let payload = z_serialize(&(offset, hasher.clone().finalize().to_vec()));
+                put(&end_keyexpr, payload.clone());
+                end = Some((payload, Instant::now()));
+                break;
+            }
+            hasher.update(&chunk);
+            if !acknowledged(&receivers, offset, chunk.len()) {
+                send_chunk(offset, &chunk);
+                unacked.insert(offset, (chunk.clone(), Instant::now()));
+            }
+            offset += chunk.len() as u64;
+        }
+
+        let kept = unacked.keys().next().copied().unwrap_or(offset);
+        track(
+            &mut receivers,
+            &mut ignored,
+            query_acks(s, &ack_keyexpr),
+            kept,
+        );
+        if !receivers.is_empty() && receivers.values().all(|r| r.ack.0) {
+            return;
+        }
+        let before = unacked.len();
+        unacked.retain(|offset, (chunk, _)| !acknowledged(&receivers, *offset, chunk.len()));
+
+        for (offset, (chunk, sent)) in unacked.iter_mut() {
+            if sent.elapsed() >= RETRANSMIT_TIMEOUT {
+                send_chunk(*offset, chunk);
+                *sent = Instant::now();
+            }
+        }
+        if let Some((payload, sent)) = end.as_mut() {
+            if sent.elapsed() >= RETRANSMIT_TIMEOUT {
+                put(&end_keyexpr, payload.clone());
+                *sent = Instant::now();
+            }
+        }
+
+        // Keep reading stdin as long as chunks get acknowledged
+        if unacked.len() == before || end.is_some() {
+            std::thread::sleep(ACK_PERIOD);
+        }
+    }
+}
+
+/// Query the acknowledgements of the receivers by key expression, exit if one failed
+fn query_acks(s: &Session, keyexpr: &KeyExpr) -> Vec<(String, Ack)> {
+    // Each receiver replies on its own key expression, all the replies are needed
+    let replies = s
+        .get(keyexpr)
+        .target(QueryTarget::All)
+        .consolidation(ConsolidationMode::None)
+        .timeout(ACK_TIMEOUT)
+        .wait()
+        .unwrap();
+    let mut acks = vec![];
+    // Error replies are timeouts, the receivers which did not reply in time are still known
+    for sample in replies.iter().filter_map(|reply| reply.into_result().ok()) {
+        let Ok(ack) = z_deserialize::<Ack>(sample.payload()) else {
+            continue;
+        };
+        if !ack.2.is_empty() {
+            eprintln!("Receiver failed: {}", ack.2);
+            std::process::exit(-1);
+        }
+        acks.push((sample.key_expr().to_string(), ack));
+    }
+    acks
+}
+
+/// Open `path` to append to it, hashing the bytes it already contains
+fn open_output(path: &Path, hasher: &mut Sha256) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut buf = vec![0; 1 << 16];
+    let mut len = 0;
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok((file, len)),
+            n => {
+                hasher.update(&buf[..n]);
+                len += n as u64;
+            }
+        }
+    }
+}
+
+/// An event received by a receiver
+enum Event {
+    Chunk(Sample),
+    End(Sample),
+    Query(Query),
+}
+
+/// Receive chunks, write them in order and acknowledge them until the transfer is done
+pub(crate) fn recv(s: &Session, params: RecvParams) {
+    let RecvParams {
+        keyexpr,
+        output: path,
+    } = params;
+
+    let mut hasher = Sha256::new();
+    // The number of bytes written, and the chunks received out of order by offset
+    let mut written = 0u64;
+    let mut pending: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+    // The transfer is resumed after the bytes already in the output file
+    let mut output: Box<dyn Write> = match &path {
+        Some(path) => match open_output(path, &mut hasher) {
+            Ok((file, len)) => {
+                written = len;
+                Box::new(file)
+            }
+            Err(e) => {
+                eprintln!("Failed to open {}: {e}", path.display());
+                std::process::exit(-1);
+            }
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+    if written > 0 {
+        eprintln!("Resuming transfer after {written} bytes");
+    }
+
+    let ack_keyexpr = keyexpr.join(&format!("{ACK_SUFFIX}/{}", s.zid())).unwrap();
+    let (tx, rx) = std::sync::mpsc::sync_channel::<Event>(CHANNEL_SIZE);
+    let (tx_end, tx_query) = (tx.clone(), tx.clone());
+    let _chunks = s
+        .declare_subscriber(keyexpr.join(CHUNK_SUFFIX).unwrap().join("*").unwrap())
+        .callback(move |sample| {
+            let _ = tx.send(Event::Chunk(sample));
+        })
+        .wait()
+        .unwrap();
+    let _end = s
+        .declare_subscriber(keyexpr.join(END_SUFFIX).unwrap())
+        .callback(move |sample| {
+            let _ = tx_end.send(Event::End(sample));
+        })
+        .wait()
+        .unwrap();
+    let _ack = s
+        .declare_queryable(&ack_keyexpr)
+        .callback(move |query| {
+            let _ = tx_query.send(Event::Query(query));
+        })
+        .wait()
+        .unwrap();
+
+    let mut end: Option<End> = None;
+    // The verification result once all the chunks are written
+    let mut result: Option<Result<(), String>> = None;
+    let mut linger: Option<Instant> = None;
+
+    loop {
+        let event = match linger {
+            Some(deadline) => {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match rx.recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+        };
+
+        match event {
+            Event::Chunk(sample) => {
+                let offset = sample
+                    .key_expr()
+                    .as_str()
+                    .rsplit('/')
+                    .next()
+                    .and_then(|offset| offset.parse::<u64>().ok());
+                let payload = sample.payload();
+                let Some(offset) = offset.filter(|o| o + payload.len() as u64 > written) else {
+                    continue;
+                };
+                pending
+                    .entry(offset)
+                    .or_insert_with(|| payload.to_bytes().into_owned());
+                // Chunks may overlap the written bytes if the chunk size changed on resume
+                while let Some(entry) = pending.first_entry() {
+                    if *entry.key() > written {
+                        break;
+                    }
+                    let (offset, chunk) = entry.remove_entry();
+                    let chunk = &chunk[((written - offset) as usize).min(chunk.len())..];
+                    if let Err(e) = output.write_all(chunk) {
+                        eprintln!("Failed to write the output: {e}");
+                        std::process::exit(-1);
+                    }
+                    hasher.update(chunk);
+                    written += chunk.len() as u64;
+                }
+                output.flush().unwrap();
+            }
+            Event::End(sample) => {
+                if let Ok(e) = z_deserialize::<End>(sample.payload()) {
+                    end = Some(e);
+                }
+            }
+            Event::Query(query) => {
+                let mut ranges = vec![(0, written)];
+                for (offset, chunk) in &pending {
+                    let chunk_end = offset + chunk.len() as u64;
+                    match ranges.last_mut() {
+                        Some((_, end)) if *end >= *offset => *end = chunk_end.max(*end),
+                        _ => ranges.push((*offset, chunk_end)),
+                    }
+                }
+                let error = match &result {
+                    Some(Err(e)) => e.clone(),
+                    _ => String::new(),
+                };
+                let ack: Ack = (result.is_some(), ranges, error);
+                query.reply(&ack_keyexpr, z_serialize(&ack)).wait().unwrap();
+                // The sender knows the outcome of the transfer
+                if result.is_some() {
+                    break;
+                }
+            }
+        }
+
+        if let (None, Some((len, digest))) = (&result, &end) {
+            if written >= *len {
+                result = Some(if written != *len {
+                    Err(format!("expected {len} bytes, received {written}"))
+                } else if hasher.clone().finalize().as_slice() != digest.as_slice() {
+                    Err("SHA-256 mismatch".to_string())
+                } else {
+                    Ok(())
+                });
+                linger = Some(Instant::now() + LINGER);
+            }
+        }
+    }
+
+    if let Some(Err(e)) = result {
+        eprintln!("Transfer failed: {e}");
+        // The bytes the transfer was resumed after may not be the ones sent, start over next time
+        if let Some(path) = path {
+            drop(output);
+            match File::create(&path) {
+                Ok(_) => eprintln!(
+                    "Truncated {}, the next transfer starts over",
+                    path.display()
+                ),
+                Err(e) => eprintln!("Failed to truncate {}: {e}", path.display()),
+            }
+        }
+        std::process::exit(-1);
+    }
+    if result.is_none() {
+        eprintln!("Transfer interrupted");
+        std::process::exit(-1);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::utils::UdpParams;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use zenoh::sample::{Locality, SampleKind};
+use zenoh::{Session, Wait};
+
+/********************/
+/*       UDP        */
+/********************/
+/// Publish the datagrams received on the bind address, one sample per datagram,
+/// and send the samples received from other sessions to the target address
+pub(crate) fn bridge(s: &Session, params: UdpParams) {
+    let UdpParams {
+        keyexpr,
+        bind,
+        target,
+        reliability,
+        congestion_control,
+        priority,
+        express,
+        buffer,
+    } = params;
+    let target = target.map(|addr| match addr.to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(target)) => target,
+        Ok(None) => {
+            eprintln!("Failed to resolve {addr}");
+            std::process::exit(-1);
+        }
+        Err(e) => {
+            eprintln!("Failed to resolve {addr}: {e}");
+            std::process::exit(-1);
+        }
+    });
+    // Replies of the target are received on an ephemeral port by default
+    let bind = bind.unwrap_or_else(|| match target {
+        Some(SocketAddr::V6(_)) => "[::]:0".to_string(),
+        _ => "0.0.0.0:0".to_string(),
+    });
+    let socket = match UdpSocket::bind(&bind) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Failed to bind {bind}: {e}");
+            std::process::exit(-1);
+        }
+    };
+
+    let p = s
+        .declare_publisher(&keyexpr)
+        .reliability(reliability)
+        .congestion_control(congestion_control)
+        .priority(priority)
+        .express(express)
+        .wait()
+        .unwrap();
+    // The datagrams published by this session are not sent back
+    let _sub = target.map(|target| {
+        let socket = socket.try_clone().unwrap();
+        s.declare_subscriber(&keyexpr)
+            .allowed_origin(Locality::Remote)
+            .callback(move |sample| {
+                if sample.kind() != SampleKind::Put {
+                    return;
+                }
+                if let Err(e) = socket.send_to(&sample.payload().to_bytes(), target) {
+                    eprintln!("Failed to send a datagram to {target}: {e}");
+                }
+            })
+            .wait()
+            .unwrap()
+    });
+
+    // Datagrams larger than the buffer are truncated, the extra byte detects them
+    let mut buf = vec![0u8; buffer + 1];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) if len > buffer => {
+                eprintln!("Dropping a datagram of more than {buffer} bytes from {from}");
+            }
+            Ok((len, _)) => p.put(&buf[..len]).wait().unwrap(),
+            // e.g. the target port is unreachable, the next datagrams may go through
+            Err(e) => eprintln!("Failed to receive a datagram: {e}"),
+        }
+    }
+}
@@ -1,9 +1,15 @@
+use crate::{
+    attachment::{self, Attachment},
+    framing::{self, Framing},
+};
 use serde_json::json;
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 use zenoh::{
+    bytes::Encoding,
     config::{Config, WhatAmI},
     key_expr::KeyExpr,
     qos::{CongestionControl, Priority, Reliability},
+    query::{ConsolidationMode, QueryTarget, Selector},
 };
 
 /********************/
@@ -16,34 +22,389 @@ enum CliCommand {
     Read {
         /// The zenoh key expression to read from
         keyexpr: String,
-        /// Do not exit on EOF
-        #[arg(short = 'i', long)]
-        ignore_eof: bool,
+        #[command(flatten)]
+        args: ReadArgs,
+        #[command(flatten)]
+        stream: StreamArgs,
     },
     /// Read from stdin and write to zenoh
     #[clap(short_flag = 'w')]
     Write {
         /// The zenoh key expression to write on
         keyexpr: String,
-        /// The zenoh reliability to use for writing
+        #[command(flatten)]
+        args: WriteArgs,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+    /// Query zenoh and write the replies to stdout
+    #[clap(short_flag = 'g')]
+    Get {
+        /// The zenoh selector to query, e.g. "key/expr?arg=value"
+        selector: String,
+        /// The zenoh query target
         #[arg(short = 't', long)]
-        #[clap(value_parser(["reliable", "besteffort"]))]
-        reliability: Option<String>,
-        /// The zenoh congestion control to use for writing
-        #[arg(short = 'd', long)]
-        #[clap(value_parser(["drop", "block"]))]
-        congestion_control: Option<String>,
-        /// The zenoh priority to use for writing
+        #[clap(value_parser(["best_matching", "all", "all_complete"]))]
+        target: Option<String>,
+        /// The zenoh consolidation mode
+        #[arg(short = 'o', long)]
+        #[clap(value_parser(["auto", "none", "monotonic", "latest"]))]
+        consolidation: Option<String>,
+        /// The query timeout, e.g. "500ms" or "10s"
+        #[arg(short = 'T', long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Read stdin until EOF and serve it to zenoh queries
+    #[clap(short_flag = 'q')]
+    Serve {
+        /// The zenoh key expression to serve on
+        keyexpr: String,
+        /// Declare the queryable as complete
         #[arg(short, long)]
-        #[clap(value_parser(["1", "2", "3", "4", "5", "6", "7"]))]
-        priority: Option<u8>,
-        /// The zenoh express flag to use for writing
+        complete: bool,
+        /// Run a shell command for each query and reply with its stdout instead of serving stdin
+        #[arg(short = 'x', long)]
+        exec: Option<String>,
+    },
+    /// Read from stdin and write to zenoh while reading from zenoh and writing to stdout
+    #[clap(short_flag = 'b')]
+    Pipe {
+        /// The zenoh key expression to write stdin on
+        pub_keyexpr: String,
+        /// The zenoh key expression to read stdout from
+        sub_keyexpr: String,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        write: WriteArgs,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+    /// Send stdin reliably to a receiver, retransmitting the chunks it did not acknowledge
+    Send {
+        /// The zenoh key expression to send on
+        keyexpr: String,
+        #[command(flatten)]
+        qos: QosArgs,
+    },
+    /// Receive from a sender and write to stdout, exit with an error if the transfer failed
+    Recv {
+        /// The zenoh key expression to receive from
+        keyexpr: String,
+        /// Write to the given file instead of stdout, resuming the transfer if it exists
         #[arg(short, long)]
-        express: bool,
-        /// The buffer size to read on
-        #[arg(short, long, default_value = "32768")]
-        buffer: usize,
+        output: Option<PathBuf>,
     },
+    /// Forward TCP connections through zenoh, like `ssh -L`
+    Forward {
+        /// The zenoh key expression to forward on
+        keyexpr: String,
+        /// Accept TCP connections on the given address, e.g. "127.0.0.1:2222"
+        #[arg(long, value_name = "ADDR", required_unless_present = "target")]
+        bind: Option<String>,
+        /// Connect the forwarded connections to the given address, e.g. "localhost:22"
+        #[arg(long, value_name = "ADDR", conflicts_with = "bind")]
+        target: Option<String>,
+    },
+    /// Publish UDP datagrams to zenoh and send zenoh samples as UDP datagrams, one per sample
+    Udp {
+        /// The zenoh key expression to bridge on
+        keyexpr: String,
+        /// Publish the datagrams received on the given address, e.g. "0.0.0.0:5000"
+        #[arg(long, value_name = "ADDR", required_unless_present = "target")]
+        bind: Option<String>,
+        /// Send the samples from other sessions to the given address, e.g. "10.0.0.5:5000"
+        #[arg(long, value_name = "ADDR")]
+        target: Option<String>,
+        #[command(flatten)]
+        qos: QosArgs,
+    },
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct ReadArgs {
+    /// Do not exit on EOF, same as `--eof-policy never`
+    #[arg(short = 'i', long, conflicts_with = "eof_policy")]
+    ignore_eof: bool,
+    /// Exit on EOF of the first writer, of all known writers, or never [default: "all"]
+    #[arg(long)]
+    #[clap(value_parser(["first", "all", "never"]))]
+    eof_policy: Option<String>,
+    /// Only accept samples with the given encoding, e.g. "text/plain" (can be repeated)
+    #[arg(long)]
+    accept_encoding: Vec<String>,
+    /// Print the encoding of each sample to stderr
+    #[arg(long)]
+    print_encoding: bool,
+    /// Print the attachment of each sample to stderr
+    #[arg(long)]
+    print_attachment: bool,
+    /// Exit after receiving the given number of samples
+    #[arg(short = 'n', long)]
+    count: Option<usize>,
+    /// Exit with status 124 if not done within the given duration, e.g. "30s"
+    #[arg(short = 'T', long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    /// Exit with status 124 if no sample is received within the given duration, e.g. "5s"
+    #[arg(long, value_parser = humantime::parse_duration)]
+    idle_timeout: Option<Duration>,
+    /// Also fetch past samples from storages and publisher caches, emitted before live samples
+    #[arg(long)]
+    history: bool,
+    /// Exit with an error on the first missed sample
+    #[arg(long)]
+    strict: bool,
+    /// Drop the samples not signed by the owner of the given Ed25519 PEM public key
+    #[arg(long, value_name = "PATH")]
+    verify_key: Option<PathBuf>,
+    /// Drop the samples signed more than the given duration ago or ahead, e.g. "30s"
+    #[arg(long, value_parser = humantime::parse_duration, requires = "verify_key")]
+    max_age: Option<Duration>,
+}
+
+/// QoS options shared by the commands writing to zenoh
+#[derive(clap::Args, Clone, Debug)]
+struct QosArgs {
+    /// The zenoh reliability to use for writing
+    #[arg(short = 't', long)]
+    #[clap(value_parser(["reliable", "besteffort"]))]
+    reliability: Option<String>,
+    /// The zenoh congestion control to use for writing
+    #[arg(short = 'd', long)]
+    #[clap(value_parser(["drop", "block"]))]
+    congestion_control: Option<String>,
+    /// The zenoh priority to use for writing
+    #[arg(short, long)]
+    #[clap(value_parser = clap::value_parser!(u8).range(1..=7))]
+    priority: Option<u8>,
+    /// The zenoh express flag to use for writing
+    #[arg(short, long)]
+    express: bool,
+    /// The buffer size to read on, also the maximum record size when framing is used,
+    /// or the maximum datagram size for `udp` [default: 32768, 65535 for `udp`]
+    #[arg(short, long)]
+    buffer: Option<usize>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct WriteArgs {
+    #[command(flatten)]
+    qos: QosArgs,
+    /// The encoding of the samples, e.g. "text/plain", or "auto" to detect UTF-8 text
+    #[arg(long)]
+    encoding: Option<String>,
+    /// An attachment entry to send with each sample as KEY=VALUE (can be repeated)
+    #[arg(short, long, value_parser = attachment::parse_entry)]
+    attachment: Vec<(String, String)>,
+    /// Wait for the given number of matching subscribers before reading stdin.
+    /// Beyond one, only `zat` readers are counted
+    #[arg(short = 'W', long, num_args = 0..=1, default_missing_value = "1")]
+    wait_subscribers: Option<usize>,
+    /// Exit with status 124 if the subscribers are not matched within the given duration
+    #[arg(long, value_parser = humantime::parse_duration, requires = "wait_subscribers")]
+    wait_timeout: Option<Duration>,
+    /// Exit with an error once all the matching subscribers went away
+    #[arg(long)]
+    exit_on_unmatched: bool,
+    /// Keep the last N published samples for late `--history` readers
+    #[arg(long, value_name = "N")]
+    cache: Option<usize>,
+    /// Compress each payload, readers decompress them automatically
+    #[arg(long)]
+    #[clap(value_parser(["zstd", "lz4", "gzip"]))]
+    compress: Option<String>,
+    /// Sign each sample with the given Ed25519 PEM private key
+    #[arg(long, value_name = "PATH")]
+    sign_key: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct StreamArgs {
+    /// How sample boundaries are represented on stdin and stdout
+    #[arg(short = 'F', long, conflicts_with_all = ["lines", "delimiter"])]
+    #[clap(value_parser(["raw", "lines", "u32be", "varint", "netstring"]))]
+    framing: Option<String>,
+    /// One sample per line, same as `--framing lines`
+    #[arg(long, conflicts_with = "delimiter")]
+    lines: bool,
+    /// One sample per record terminated by the given byte, e.g. "\0" or "0x1e"
+    #[arg(long, value_parser = framing::parse_byte)]
+    delimiter: Option<u8>,
+    /// How EOF is signalled: a zenoh delete on the key expression, or a put on its
+    /// `@zat/eof` control key leaving storages untouched. Readers always understand the latter,
+    /// but ignore deletes with "control" [default: "delete"]
+    #[arg(long)]
+    #[clap(value_parser(["delete", "control"]))]
+    eof_marker: Option<String>,
+    /// The format of records: raw payloads or JSON Lines with sample metadata
+    #[arg(short = 'f', long, conflicts_with_all = ["framing", "lines", "delimiter"])]
+    #[clap(value_parser(["raw", "jsonl"]))]
+    format: Option<String>,
+    /// Send a checksum of the payloads with the EOF marker, readers exit with an error on mismatch
+    #[arg(long)]
+    #[clap(value_parser(["crc32", "sha256"]))]
+    checksum: Option<String>,
+    /// Encrypt payloads with a key derived from the given file, shared by writers and readers
+    #[arg(long, value_name = "PATH")]
+    psk_file: Option<PathBuf>,
+}
+
+impl StreamArgs {
+    fn framing(&self) -> Framing {
+        if self.lines {
+            return Framing::Delimited(b'\n');
+        }
+        if let Some(delimiter) = self.delimiter {
+            return Framing::Delimited(delimiter);
+        }
+        self.framing
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "raw" => Framing::Raw,
+                "lines" => Framing::Delimited(b'\n'),
+                "u32be" => Framing::U32Be,
+                "varint" => Framing::Varint,
+                "netstring" => Framing::Netstring,
+                _ => unreachable!(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn eof_marker(&self) -> EofMarker {
+        self.eof_marker
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "delete" => EofMarker::Delete,
+                "control" => EofMarker::Control,
+                _ => unreachable!(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn format(&self) -> Format {
+        self.format
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "raw" => Format::Raw,
+                "jsonl" => Format::Jsonl,
+                _ => unreachable!(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn checksum(&self) -> Option<Checksum> {
+        self.checksum.as_ref().map(|s| match s.as_str() {
+            "crc32" => Checksum::Crc32,
+            "sha256" => Checksum::Sha256,
+            _ => unreachable!(),
+        })
+    }
+}
+
+impl ReadArgs {
+    fn params(&self, keyexpr: &str, stream: &StreamArgs) -> SubParams {
+        SubParams {
+            keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+            eof_policy: match self.ignore_eof {
+                true => EofPolicy::Never,
+                false => self
+                    .eof_policy
+                    .as_ref()
+                    .map(|s| match s.as_str() {
+                        "first" => EofPolicy::First,
+                        "all" => EofPolicy::All,
+                        "never" => EofPolicy::Never,
+                        _ => unreachable!(),
+                    })
+                    .unwrap_or_default(),
+            },
+            accept_encoding: self.accept_encoding.clone(),
+            print_encoding: self.print_encoding,
+            print_attachment: self.print_attachment,
+            count: self.count,
+            timeout: self.timeout,
+            idle_timeout: self.idle_timeout,
+            history: self.history,
+            strict: self.strict,
+            verify_key: self.verify_key.clone(),
+            max_age: self.max_age,
+            framing: stream.framing(),
+            eof_marker: stream.eof_marker(),
+            format: stream.format(),
+            checksum: stream.checksum(),
+            psk_file: stream.psk_file.clone(),
+        }
+    }
+}
+
+impl QosArgs {
+    fn reliability(&self) -> Reliability {
+        self.reliability
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "reliable" => Reliability::Reliable,
+                "besteffort" => Reliability::BestEffort,
+                _ => unreachable!(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn congestion_control(&self) -> CongestionControl {
+        self.congestion_control
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "drop" => CongestionControl::Drop,
+                "block" => CongestionControl::Block,
+                _ => unreachable!(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn buffer(&self) -> usize {
+        self.buffer.unwrap_or(32768)
+    }
+
+    fn priority(&self) -> Priority {
+        self.priority
+            .as_ref()
+            .map(|s| Priority::try_from(*s).unwrap())
+            .unwrap_or_default()
+    }
+}
+
+impl WriteArgs {
+    fn params(&self, keyexpr: &str, stream: &StreamArgs) -> PubParams {
+        PubParams {
+            keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+            reliability: self.qos.reliability(),
+            congestion_control: self.qos.congestion_control(),
+            priority: self.qos.priority(),
+            express: self.qos.express,
+            buffer: self.qos.buffer(),
+            encoding: match self.encoding.as_deref() {
+                Some("auto") => None,
+                Some(encoding) => Some(Encoding::from(encoding)),
+                None => Some(Encoding::default()),
+            },
+            attachment: self.attachment.clone(),
+            wait_subscribers: self.wait_subscribers,
+            wait_timeout: self.wait_timeout,
+            exit_on_unmatched: self.exit_on_unmatched,
+            cache: self.cache,
+            compress: self.compress.as_ref().map(|s| match s.as_str() {
+                "zstd" => Compression::Zstd,
+                "lz4" => Compression::Lz4,
+                "gzip" => Compression::Gzip,
+                _ => unreachable!(),
+            }),
+            sign_key: self.sign_key.clone(),
+            framing: stream.framing(),
+            eof_marker: stream.eof_marker(),
+            format: stream.format(),
+            checksum: stream.checksum(),
+            psk_file: stream.psk_file.clone(),
+        }
+    }
 }
 
 #[derive(clap::Parser, Debug)]
@@ -54,7 +415,16 @@ enum CliCommand {
     before_long_help = "\
 Example:
 $ zat -r zenoh/cat
-$ echo \"Meow\" | zat -w zenoh/cat"
+$ echo \"Meow\" | zat -w zenoh/cat
+$ zat -g zenoh/cat
+$ echo \"Meow\" | zat -q zenoh/cat
+$ zat -q zenoh/date -x date
+$ zat -b zenoh/cat/a zenoh/cat/b
+$ zat recv zenoh/file > file.bin
+$ cat file.bin | zat send zenoh/file
+$ zat forward zenoh/ssh --target localhost:22
+$ zat forward zenoh/ssh --bind 127.0.0.1:2222
+$ zat udp zenoh/sensor --bind 0.0.0.0:5000"
 )]
 pub(crate) struct CliArgs {
     /* zcat config */
@@ -92,42 +462,100 @@ impl CliArgs {
         match &self.command {
             CliCommand::Read {
                 keyexpr,
-                ignore_eof,
-            } => Params::Read(SubParams {
-                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
-                ignore_eof: *ignore_eof,
-            }),
+                args,
+                stream,
+            } => Params::Read(args.params(keyexpr, stream)),
             CliCommand::Write {
                 keyexpr,
-                reliability,
-                congestion_control,
-                priority,
-                express,
-                buffer,
-            } => Params::Write(PubParams {
-                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
-                reliability: reliability
+                args,
+                stream,
+            } => Params::Write(args.params(keyexpr, stream)),
+            CliCommand::Get {
+                selector,
+                target,
+                consolidation,
+                timeout,
+            } => Params::Get(GetParams {
+                selector: Selector::try_from(selector.to_string()).unwrap(),
+                target: target
                     .as_ref()
                     .map(|s| match s.as_str() {
-                        "reliable" => Reliability::Reliable,
-                        "besteffort" => Reliability::BestEffort,
+                        "best_matching" => QueryTarget::BestMatching,
+                        "all" => QueryTarget::All,
+                        "all_complete" => QueryTarget::AllComplete,
                         _ => unreachable!(),
                     })
                     .unwrap_or_default(),
-                congestion_control: congestion_control
+                consolidation: consolidation
                     .as_ref()
                     .map(|s| match s.as_str() {
-                        "drop" => CongestionControl::Drop,
-                        "block" => CongestionControl::Block,
+                        "auto" => ConsolidationMode::Auto,
+                        "none" => ConsolidationMode::None,
+                        "monotonic" => ConsolidationMode::Monotonic,
+                        "latest" => ConsolidationMode::Latest,
                         _ => unreachable!(),
                     })
                     .unwrap_or_default(),
-                priority: priority
-                    .as_ref()
-                    .map(|s| Priority::try_from(*s).unwrap())
-                    .unwrap_or_default(),
-                express: *express,
-                buffer: *buffer,
+                timeout: *timeout,
+            }),
+            CliCommand::Serve {
+                keyexpr,
+                complete,
+                exec,
+            } => Params::Serve(ServeParams {
+                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+                complete: *complete,
+                exec: exec.clone(),
+            }),
+            CliCommand::Pipe {
+                pub_keyexpr,
+                sub_keyexpr,
+                read,
+                write,
+                stream,
+            } => Params::Pipe(PipeParams {
+                publisher: write.params(pub_keyexpr, stream),
+                subscriber: read.params(sub_keyexpr, stream),
+            }),
+            CliCommand::Send { keyexpr, qos } => Params::Send(SendParams {
+                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+                reliability: qos.reliability(),
+                congestion_control: qos.congestion_control(),
+                priority: qos.priority(),
+                express: qos.express,
+                chunk_size: qos.buffer(),
+            }),
+            CliCommand::Recv { keyexpr, output } => Params::Recv(RecvParams {
+                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+                output: output.clone(),
+            }),
+            CliCommand::Forward {
+                keyexpr,
+                bind,
+                target,
+            } => Params::Forward(ForwardParams {
+                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+                role: match (bind, target) {
+                    (Some(addr), _) => ForwardRole::Bind(addr.clone()),
+                    (None, Some(addr)) => ForwardRole::Target(addr.clone()),
+                    (None, None) => unreachable!(),
+                },
+            }),
+            CliCommand::Udp {
+                keyexpr,
+                bind,
+                target,
+                qos,
+            } => Params::Udp(UdpParams {
+                keyexpr: KeyExpr::try_from(keyexpr.to_string()).unwrap(),
+                bind: bind.clone(),
+                target: target.clone(),
+                reliability: qos.reliability(),
+                congestion_control: qos.congestion_control(),
+                priority: qos.priority(),
+                express: qos.express,
+                // Large enough for any UDP datagram
+                buffer: qos.buffer.unwrap_or(65535),
             }),
         }
     }
@@ -179,6 +607,13 @@ impl CliArgs {
 pub(crate) enum Params {
     Write(PubParams),
     Read(SubParams),
+    Get(GetParams),
+    Serve(ServeParams),
+    Pipe(PipeParams),
+    Send(SendParams),
+    Recv(RecvParams),
+    Forward(ForwardParams),
+    Udp(UdpParams),
 }
 
 #[derive(Clone, Debug)]
@@ -189,10 +624,148 @@ pub(crate) struct PubParams {
     pub(crate) priority: Priority,
     pub(crate) express: bool,
     pub(crate) buffer: usize,
+    /// The encoding of the samples, `None` to detect it for each sample
+    pub(crate) encoding: Option<Encoding>,
+    pub(crate) attachment: Attachment,
+    pub(crate) wait_subscribers: Option<usize>,
+    pub(crate) wait_timeout: Option<Duration>,
+    pub(crate) exit_on_unmatched: bool,
+    /// The number of samples kept for late readers
+    pub(crate) cache: Option<usize>,
+    pub(crate) compress: Option<Compression>,
+    pub(crate) sign_key: Option<PathBuf>,
+    pub(crate) framing: Framing,
+    pub(crate) eof_marker: EofMarker,
+    pub(crate) format: Format,
+    pub(crate) checksum: Option<Checksum>,
+    pub(crate) psk_file: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct SubParams {
     pub(crate) keyexpr: KeyExpr<'static>,
-    pub(crate) ignore_eof: bool,
+    pub(crate) eof_policy: EofPolicy,
+    pub(crate) accept_encoding: Vec<String>,
+    pub(crate) print_encoding: bool,
+    pub(crate) print_attachment: bool,
+    pub(crate) count: Option<usize>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) history: bool,
+    pub(crate) strict: bool,
+    pub(crate) verify_key: Option<PathBuf>,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) framing: Framing,
+    pub(crate) eof_marker: EofMarker,
+    pub(crate) format: Format,
+    pub(crate) checksum: Option<Checksum>,
+    pub(crate) psk_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum EofPolicy {
+    /// Exit on the first EOF received
+    First,
+    /// Exit once all the writers seen so far reached EOF
+    #[default]
+    All,
+    /// Never exit on EOF
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum EofMarker {
+    /// A zenoh delete on the key expression
+    #[default]
+    Delete,
+    /// A put on the `@zat/eof` control key of the key expression
+    Control,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// Payloads only, framed according to [`Framing`]
+    #[default]
+    Raw,
+    /// One JSON object per sample carrying the payload and its metadata
+    Jsonl,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Checksum {
+    Crc32,
+    Sha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Zstd,
+    Lz4,
+    Gzip,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GetParams {
+    pub(crate) selector: Selector<'static>,
+    pub(crate) target: QueryTarget,
+    pub(crate) consolidation: ConsolidationMode,
+    pub(crate) timeout: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ServeParams {
+    pub(crate) keyexpr: KeyExpr<'static>,
+    pub(crate) complete: bool,
+    pub(crate) exec: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PipeParams {
+    pub(crate) publisher: PubParams,
+    pub(crate) subscriber: SubParams,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct SendParams {
+    pub(crate) keyexpr: KeyExpr<'static>,
+    pub(crate) reliability: Reliability,
+    pub(crate) congestion_control: CongestionControl,
+    pub(crate) priority: Priority,
+    pub(crate) express: bool,
+    pub(crate) chunk_size: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct RecvParams {
+    pub(crate) keyexpr: KeyExpr<'static>,
+    pub(crate) output: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ForwardParams {
+    pub(crate) keyexpr: KeyExpr<'static>,
+    pub(crate) role: ForwardRole,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ForwardRole {
+    /// Accept connections on the address and forward them to the target side
+    Bind(String),
+    /// Connect the forwarded connections to the address
+    Target(String),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct UdpParams {
+    pub(crate) keyexpr: KeyExpr<'static>,
+    /// The address to receive datagrams on, an ephemeral port if `None`
+    pub(crate) bind: Option<String>,
+    /// The address to send datagrams to, samples are not subscribed to if `None`
+    pub(crate) target: Option<String>,
+    pub(crate) reliability: Reliability,
+    pub(crate) congestion_control: CongestionControl,
+    pub(crate) priority: Priority,
+    pub(crate) express: bool,
+    /// The maximum datagram size
+    pub(crate) buffer: usize,
 }